@@ -1,324 +1,5602 @@
-use clap::{App, Arg, ArgMatches};
-use std::collections::HashSet;
+use clap::{App, Arg, ArgAction, ArgGroup, ArgMatches, ValueSource};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
-use std::fs::File;
-use std::io::{stdin, stdout, BufRead, BufReader};
+use std::fs::{self, File};
+use std::io::{
+    self, stderr, stdin, stdout, BufRead, BufReader, BufWriter, IsTerminal, Read, Write,
+};
+use std::process::{Command, Stdio};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use csv::StringRecord;
+use csv::{ByteRecord, QuoteStyle, StringRecord};
+use regex::Regex;
 use std::ops::Range;
+use std::path::{Path, PathBuf};
 
 #[derive(Debug)]
 pub struct Config {
     files: Vec<String>,
     selector: Selector,
     delimiter: u8, // This is a u8 because csv parser supports only byte delimiters!
+    output_delimiter: u8,
+    interleave: bool,
+    paste: bool,
+    describe: bool,
+    sample: usize,
+    join: Option<String>,
+    pipe_to: Option<String>,
+    empty_report: bool,
+    hash_fields: Option<Positions>,
+    split_output: Option<(String, usize)>,
+    headers_only: bool,
+    sort_by: Option<Vec<SortKey>>,
+    detect_encoding: bool,
+    /// What to decode the input from before cutting it, if it isn't UTF-8 already
+    encoding: Option<String>,
+    stats: Option<Arc<Stats>>,
+    progress: Option<Arc<Progress>>,
+    /// What to decompress all the input from, whatever the files are called
+    decompress: Option<Compression>,
+    show_ranges: bool,
+    /// Under --color, whether to colour in the selected fields of the lines we write out whole
+    color: Option<bool>,
+    with_filename: bool,
+    /// How many lines to write out of each file at most
+    max_lines: Option<usize>,
+    keep_going: bool,
+    hex: bool,
+    na: Option<String>,
+    min_length: usize,
+    char_safe_bytes: bool,
+    group_separator: Option<String>,
+    repeat: usize,
+    skip_lines: usize,
+    /// The values of any fields selected by name which a FILE's header hasn't got, by name
+    defaults: Vec<(String, String)>,
+    follow: bool,
+    quoting: Quoting,
+    max_output_bytes: Option<usize>,
+    whole_lines: bool,
+    write_bom: bool,
+    terminator: Terminator,
+    bytes_total: bool,
+    retry_on_lock: u32,
+    head_fields: Option<usize>,
+    null_ratio: bool,
+    missing_as: String,
+    pretty_errors: bool,
+    group_sum: Option<(usize, usize)>,
+    hash_sample: Option<(usize, f64)>,
+    only_delimited: bool,
+    trim: bool,
+    header: bool,
+    keep_order: bool,
+    reverse: bool,
+    zero_terminated: bool,
+    no_split: bool,
+    regex_delim: Option<Regex>,
+    str_delim: Option<String>,
+    /// What to join the output fields with when it's more than the csv writer's single byte
+    joiner: Option<String>,
+    squeeze: bool,
+    output_format: OutputFormat,
+    line_numbers: bool,
+    keep_cr: bool,
+    no_quoting: bool,
+    jobs: usize,
+    strict_utf8: bool,
+    output: Option<String>,
+    count_fields: bool,
+    strict_ranges: bool,
+    zero_based: bool,
+    tail_anchored: bool,
 }
 
-#[derive(Debug)]
-enum Selector {
+/// What to cut out of each line, by 0-based position for all but `Keys`.
+#[derive(Debug, Clone)]
+pub enum Selector {
     Bytes(Positions),
     Chars(Positions),
     Fields(Positions),
+    /// The keys to pick out of each line of NDJSON input
+    Keys(Vec<String>),
+    /// The names of the fields to pick out, as given by the header line
+    Names(Vec<String>),
+    /// What the names of the fields to pick out match, as given by the header line
+    NamesMatching(Regex),
+}
+
+pub type Positions = Vec<Range<usize>>;
+
+/// Parses a selector given as `b:`, `c:` or `f:` followed by a list of bytes, chars or fields like
+/// `-b`, `-c` or `-f` would take, as in `"f:1,3"`.
+impl std::str::FromStr for Selector {
+    type Err = KatError;
+
+    fn from_str(spec: &str) -> CliResult<Self> {
+        match spec.split_once(':') {
+            Some(("b", list)) => Ok(Selector::Bytes(parse_positions(list, false)?)),
+            Some(("c", list)) => Ok(Selector::Chars(parse_positions(list, false)?)),
+            Some(("f", list)) => Ok(Selector::Fields(parse_positions(list, false)?)),
+            _ => Err(format!(
+                "kat: bad selector: '{}' (it has to start with b:, c: or f:)",
+                spec
+            )
+            .into()),
+        }
+    }
+}
+
+/// Builds a `Config` for using kat as a library. Whatever isn't set is as it would be with no
+/// options on the command line, except that there has to be something to select.
+#[derive(Debug, Default)]
+pub struct ConfigBuilder {
+    files: Vec<String>,
+    selector: Option<CliResult<Selector>>,
+    delimiter: Option<String>,
+    output_delimiter: Option<String>,
+}
+
+impl ConfigBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The files to cut, where "-" is stdin. Defaults to just stdin.
+    pub fn files<S: Into<String>>(mut self, files: impl IntoIterator<Item = S>) -> Self {
+        self.files = files.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Selects the fields in `list`, given the way --fields takes them. Like each of the ways of
+    /// selecting, this replaces any earlier selection.
+    pub fn fields(mut self, list: &str) -> Self {
+        self.selector = Some(parse_positions(list, false).map(Selector::Fields));
+        self
+    }
+
+    pub fn chars(mut self, list: &str) -> Self {
+        self.selector = Some(parse_positions(list, false).map(Selector::Chars));
+        self
+    }
+
+    pub fn bytes(mut self, list: &str) -> Self {
+        self.selector = Some(parse_positions(list, false).map(Selector::Bytes));
+        self
+    }
+
+    pub fn selector(mut self, selector: Selector) -> Self {
+        self.selector = Some(Ok(selector));
+        self
+    }
+
+    /// The field delimiter, which has to come down to a single byte once escapes like `\t` are
+    /// taken into account. Defaults to a tab.
+    pub fn delimiter(mut self, delimiter: &str) -> Self {
+        self.delimiter = Some(delimiter.to_owned());
+        self
+    }
+
+    /// Defaults to the field delimiter.
+    pub fn output_delimiter(mut self, delimiter: &str) -> Self {
+        self.output_delimiter = Some(delimiter.to_owned());
+        self
+    }
+
+    pub fn build(self) -> CliResult<Config> {
+        let delimiter = unescape(self.delimiter.as_deref().unwrap_or("\t"));
+        if delimiter.is_empty() {
+            return Err(
+                "kat: the delimiter can't be empty (leave -d out, or use --tab, for a tab)".into(),
+            );
+        }
+        if delimiter.len() != 1 {
+            return Err(KatError::BadDelimiter);
+        }
+        let output_delimiter = match &self.output_delimiter {
+            Some(output_delimiter) => unescape(output_delimiter),
+            None => delimiter.clone(),
+        };
+        if output_delimiter.len() != 1 {
+            return Err(KatError::BadOutputDelimiter);
+        }
+        let selector = match self.selector {
+            Some(selector) => selector?,
+            None => return Err("Must have --fields, --bytes, or --chars".into()),
+        };
+        let files = match self.files.is_empty() {
+            true => vec!["-".to_owned()],
+            false => self.files,
+        };
+        Ok(Config {
+            files,
+            selector,
+            delimiter: delimiter.as_bytes()[0],
+            output_delimiter: output_delimiter.as_bytes()[0],
+            interleave: false,
+            paste: false,
+            describe: false,
+            sample: 100,
+            join: None,
+            pipe_to: None,
+            empty_report: false,
+            hash_fields: None,
+            split_output: None,
+            headers_only: false,
+            sort_by: None,
+            detect_encoding: false,
+            encoding: None,
+            stats: None,
+            progress: None,
+            decompress: None,
+            show_ranges: false,
+            color: None,
+            with_filename: false,
+            max_lines: None,
+            keep_going: false,
+            hex: false,
+            na: None,
+            min_length: 0,
+            char_safe_bytes: false,
+            group_separator: None,
+            repeat: 1,
+            skip_lines: 0,
+            defaults: Vec::new(),
+            follow: false,
+            quoting: Quoting::Necessary,
+            max_output_bytes: None,
+            whole_lines: false,
+            write_bom: false,
+            terminator: Terminator::Lf,
+            bytes_total: false,
+            retry_on_lock: 0,
+            head_fields: None,
+            null_ratio: false,
+            missing_as: String::new(),
+            pretty_errors: false,
+            group_sum: None,
+            hash_sample: None,
+            only_delimited: false,
+            trim: false,
+            header: false,
+            keep_order: false,
+            reverse: false,
+            zero_terminated: false,
+            no_split: false,
+            regex_delim: None,
+            str_delim: None,
+            joiner: None,
+            squeeze: false,
+            output_format: OutputFormat::Delimited,
+            line_numbers: false,
+            keep_cr: false,
+            no_quoting: false,
+            jobs: 1,
+            strict_utf8: false,
+            output: None,
+            count_fields: false,
+            strict_ranges: false,
+            zero_based: false,
+            tail_anchored: false,
+        })
+    }
+}
+
+/// The end of an open-ended range like `3-`, which runs to the end of whatever line it's applied
+/// to.
+const OPEN_END: usize = usize::MAX;
+
+/// Positions from here on count back from the end of the line, this one being the last, as with
+/// `-1` under --tail-anchored. They're only known once we have a line to apply them to.
+const FROM_END: usize = OPEN_END / 2;
+
+/// How far from the end of the line `idx` is if it counts back from there, 1 being the last.
+fn from_end(idx: usize) -> Option<usize> {
+    (FROM_END..OPEN_END)
+        .contains(&idx)
+        .then(|| idx - FROM_END + 1)
+}
+
+/// The set of (0-based) indices picked out by some positions, kept as sorted ranges which
+/// neither overlap nor touch so that a line can be cut by walking through them in turn.
+#[derive(Clone, Debug, Default)]
+struct Indices {
+    ranges: Positions,
+}
+
+impl Indices {
+    fn new(positions: &Positions) -> Self {
+        Indices {
+            ranges: merge_ranges(positions),
+        }
+    }
+
+    fn contains(&self, idx: &usize) -> bool {
+        let next = self.ranges.partition_point(|r| r.end <= *idx);
+        self.ranges.get(next).is_some_and(|r| r.start <= *idx)
+    }
+
+    /// The selected indices in ascending order, up to `len` for a line or record of that length.
+    fn of(&self, len: usize) -> impl DoubleEndedIterator<Item = usize> + '_ {
+        self.ranges
+            .iter()
+            .flat_map(move |r| r.start.min(len)..r.end.min(len))
+    }
+
+    /// The selected indices in ascending order, with only open-ended ranges stopping short of
+    /// `len`.
+    fn up_to(&self, len: usize) -> Vec<usize> {
+        self.ranges
+            .iter()
+            .flat_map(|r| r.start..if r.end == OPEN_END { len } else { r.end })
+            .collect()
+    }
+}
+
+/// Sorts the ranges by where they start, merging any which overlap or touch.
+fn merge_ranges(positions: &Positions) -> Positions {
+    let mut sorted = positions.clone();
+    sorted.sort_by_key(|r| r.start);
+    let mut merged: Positions = Vec::with_capacity(sorted.len());
+    for range in sorted {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+/// The positions to pick out of each line or record, both as listed and as a set.
+#[derive(Clone)]
+struct Selection {
+    positions: Positions,
+    indices: Indices,
+    keep_order: bool,
+    /// Whether to pick the indices out in descending order instead
+    reverse: bool,
+    /// Whether any of the positions count back from the end of the line
+    anchored: bool,
+    /// How many times over to pick out each index, one after the other
+    repeat: usize,
+}
+
+impl Selection {
+    fn new(positions: &Positions, keep_order: bool) -> Self {
+        Selection {
+            positions: positions.clone(),
+            indices: Indices::new(positions),
+            keep_order,
+            reverse: false,
+            anchored: positions.iter().any(|r| from_end(r.start).is_some()),
+            repeat: 1,
+        }
+    }
+
+    fn reversed(self, reverse: bool) -> Self {
+        Selection { reverse, ..self }
+    }
+
+    fn repeated(self, repeat: usize) -> Self {
+        Selection { repeat, ..self }
+    }
+
+    /// The selection for a line or record of `len` items, with any positions counting back from
+    /// the end of it turned into the indices they stand for there. Those which would fall before
+    /// the start of it are dropped, and the rest are picked out in the same order (and only once
+    /// each) as any other position.
+    fn at(&self, len: usize) -> Cow<'_, Selection> {
+        if !self.anchored {
+            return Cow::Borrowed(self);
+        }
+        let positions = self
+            .positions
+            .iter()
+            .filter_map(|r| match from_end(r.start) {
+                Some(back) if back <= len => Some(len - back..len - back + 1),
+                Some(_) => None,
+                None => Some(r.clone()),
+            })
+            .collect();
+        let selection = Selection::new(&positions, self.keep_order);
+        Cow::Owned(selection.reversed(self.reverse).repeated(self.repeat))
+    }
+
+    /// The (0-based) indices to pick out of a line or record of `len` items, either in the order
+    /// they were listed, repeats and all, or once each in ascending (or descending) order.
+    fn of(&self, len: usize) -> Box<dyn Iterator<Item = usize> + '_> {
+        let indices: Box<dyn Iterator<Item = usize>> = match (self.keep_order, self.reverse) {
+            (true, _) => Box::new(
+                self.positions
+                    .iter()
+                    .flat_map(move |r| r.start..r.end.min(len)),
+            ),
+            (false, true) => Box::new(self.indices.of(len).rev()),
+            (false, false) => Box::new(self.indices.of(len)),
+        };
+        match self.repeat {
+            1 => indices,
+            n => Box::new(indices.flat_map(move |idx| std::iter::repeat_n(idx, n))),
+        }
+    }
+}
+
+/// Adds the selected bytes of `line` to `selected`, separating each region of consecutive bytes
+/// with `join` if there is one.
+fn select_bytes(line: &[u8], selection: &Selection, join: Option<&str>, selected: &mut Vec<u8>) {
+    let mut prev = None;
+    for idx in selection.of(line.len()) {
+        if let (Some(join), Some(prev)) = (join, prev) {
+            if idx != prev + 1 {
+                selected.extend_from_slice(join.as_bytes());
+            }
+        }
+        selected.push(line[idx]);
+        prev = Some(idx);
+    }
+}
+
+/// Like `select_bytes` except that only the chars which are selected in their entirety are kept,
+/// so that a multi-byte char is never split. Lines which aren't valid UTF-8 are cut byte by byte.
+fn select_whole_chars(
+    line: &[u8],
+    selection: &Selection,
+    join: Option<&str>,
+    selected: &mut Vec<u8>,
+) {
+    let line = match std::str::from_utf8(line) {
+        Ok(line) => line,
+        Err(_) => return select_bytes(line, selection, join, selected),
+    };
+    // The last byte of the last char picked
+    let mut prev = None;
+    for idx in selection.of(line.len()) {
+        // Every byte of a char is picked when we get to its first byte, or not at all
+        if let Some(c) = line.get(idx..).and_then(|rest| rest.chars().next()) {
+            let char_bytes = idx..idx + c.len_utf8();
+            if char_bytes.clone().all(|i| selection.indices.contains(&i)) {
+                if let (Some(join), Some(prev)) = (join, prev) {
+                    if idx != prev + 1 {
+                        selected.extend_from_slice(join.as_bytes());
+                    }
+                }
+                prev = Some(char_bytes.end - 1);
+                selected.extend_from_slice(&line.as_bytes()[char_bytes]);
+            }
+        }
+    }
+}
+
+/// Adds the selected bytes of `line` to `selected`, whole chars only with --no-split or
+/// --char-safe-bytes.
+fn select_line_bytes(line: &[u8], selection: &Selection, config: &Config, selected: &mut Vec<u8>) {
+    let join = config.join.as_deref();
+    if config.no_split {
+        select_whole_chars(line, selection, join, selected)
+    } else if config.char_safe_bytes {
+        select_enclosing_chars(line, selection, join, selected)
+    } else {
+        select_bytes(line, selection, join, selected)
+    }
+}
+
+/// The text to write out for the bytes cut from line `line_num`. Since it's possible for us to
+/// pick off random bytes from a multi-byte seq in a UTF-8 file, it's important to create a lossy
+/// string to avoid an error at runtime, unless we're to be --strict-utf8 about it.
+fn cut_bytes_text<'b>(
+    bytes: &'b [u8],
+    line_num: usize,
+    config: &Config,
+) -> CliResult<Cow<'b, str>> {
+    if config.hex {
+        Ok(Cow::Owned(hex(bytes)))
+    } else if config.strict_utf8 {
+        let cut = std::str::from_utf8(bytes).map_err(|e| KatError::CutInvalidUtf8 {
+            line_num,
+            offset: e.valid_up_to(),
+        })?;
+        Ok(Cow::Borrowed(cut))
+    } else {
+        Ok(String::from_utf8_lossy(bytes))
+    }
+}
+
+/// Cuts bytes just as `select_lines` does, but reads every line into the same buffer (and cuts it
+/// into another) rather than allocating a fresh one for each, which is most of the work of cutting
+/// bytes out of short lines.
+fn cut_bytes(
+    mut reader: impl BufRead,
+    positions: &Positions,
+    config: &Config,
+    out: &mut impl Write,
+) -> CliResult<()> {
+    let selection = Selection::new(positions, config.keep_order);
+    let separator = if config.zero_terminated { b'\0' } else { b'\n' };
+    let max_lines = config.max_lines.unwrap_or(usize::MAX);
+    let mut line = Vec::new();
+    let mut byte_buf = Vec::new();
+    // Any lines skipped still count towards the line numbers
+    let mut line_num = config.skip_lines;
+    let mut written = 0;
+    while written < max_lines {
+        line.clear();
+        if reader.read_until(separator, &mut line)? == 0 {
+            break;
+        }
+        line_num += 1;
+        if line.last() == Some(&separator) {
+            line.pop();
+        }
+        if !config.zero_terminated && !config.keep_cr && line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        if !cut_line_bytes(&line, line_num, &selection, config, &mut byte_buf, out)? {
+            continue;
+        }
+        out.write_all(config.terminator.as_bytes())?;
+        if config.follow {
+            out.flush()?;
+        }
+        written += 1;
+    }
+    Ok(())
+}
+
+/// Writes out the bytes cut from `line`, line `line_num` less its terminator, cutting them into
+/// `byte_buf` on the way. This is all there is to cutting a line of bytes, whether it's for
+/// `cut_bytes` or `select_lines`, bar reading it. Hands back false, having written nothing, for a
+/// line too short for --min-length.
+fn cut_line_bytes(
+    line: &[u8],
+    line_num: usize,
+    selection: &Selection,
+    config: &Config,
+    byte_buf: &mut Vec<u8>,
+    out: &mut impl Write,
+) -> CliResult<bool> {
+    if line.len() < config.min_length {
+        count_skipped(config);
+        return Ok(false);
+    }
+    check_range(selection, line.len(), line_num, "byte", config)?;
+    byte_buf.clear();
+    select_line_bytes(line, &selection.at(line.len()), config, byte_buf);
+    let cut = cut_bytes_text(byte_buf, line_num, config)?;
+    // Just the line number and the delimiter after it, if there's to be one
+    out.write_all(numbered(line_num, String::new(), config).as_bytes())?;
+    out.write_all(cut.as_bytes())?;
+    Ok(true)
+}
+
+/// Like `select_bytes` except that a char any byte of which is selected is kept whole, so that
+/// what's picked out of a line of UTF-8 is always UTF-8 too. Lines which aren't valid UTF-8 are
+/// cut byte by byte.
+fn select_enclosing_chars(
+    line: &[u8],
+    selection: &Selection,
+    join: Option<&str>,
+    selected: &mut Vec<u8>,
+) {
+    let line = match std::str::from_utf8(line) {
+        Ok(line) => line,
+        Err(_) => return select_bytes(line, selection, join, selected),
+    };
+    // The bytes of the last char picked
+    let mut prev: Option<Range<usize>> = None;
+    for idx in selection.of(line.len()) {
+        let start = (0..=idx).rev().find(|&i| line.is_char_boundary(i)).unwrap();
+        if prev.as_ref().is_some_and(|prev| prev.start == start) {
+            continue;
+        }
+        let char_bytes = start..start + line[start..].chars().next().unwrap().len_utf8();
+        if let (Some(join), Some(prev)) = (join, &prev) {
+            if start != prev.end {
+                selected.extend_from_slice(join.as_bytes());
+            }
+        }
+        selected.extend_from_slice(&line.as_bytes()[char_bytes.clone()]);
+        prev = Some(char_bytes);
+    }
+}
+
+/// Writes out `bytes` as two hex digits apiece, separated by spaces.
+fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Picks the selected chars out of `line`, separating each region of consecutive chars with
+/// `join` if there is one.
+fn select_chars(line: &str, selection: &Selection, join: Option<&str>) -> String {
+    let chars = line.chars().collect::<Vec<_>>();
+    let mut selected = String::new();
+    let mut prev = None;
+    for idx in selection.of(chars.len()) {
+        // Any jump, backwards included, starts a new region
+        if let (Some(join), Some(prev)) = (join, prev) {
+            if idx != prev + 1 {
+                selected.push_str(join);
+            }
+        }
+        selected.push(chars[idx]);
+        prev = Some(idx);
+    }
+    selected
+}
+
+/// Picks the fields at `positions` out of `record` the way kat cuts them, in ascending order and
+/// once each however often they're listed. Positions past the end of the record are left out.
+pub fn select_fields(record: &StringRecord, positions: &Positions) -> Vec<String> {
+    let selection = Selection::new(positions, false);
+    selected_fields(
+        record,
+        &selection.at(record.len()),
+        &Indices::default(),
+        false,
+        None,
+    )
+    .into_iter()
+    .map(Cow::into_owned)
+    .collect()
+}
+
+/// The fields of `record` picked out by `selection`, with any in `hash_pos` hashed.
+fn selected_fields<'r>(
+    record: &'r StringRecord,
+    selection: &Selection,
+    hash_pos: &Indices,
+    trim: bool,
+    na: Option<&str>,
+) -> Vec<Cow<'r, str>> {
+    selection
+        .of(record.len())
+        .map(|idx| field_at(record, idx, hash_pos, trim, na))
+        .collect()
+}
+
+/// Field `idx` of `record` as it's written out, trimmed of any whitespace around it if we're to
+/// `trim` it and then hashed if it's in `hash_pos`. What's left empty is written as `na` instead
+/// if there is one.
+fn field_at<'r>(
+    record: &'r StringRecord,
+    idx: usize,
+    hash_pos: &Indices,
+    trim: bool,
+    na: Option<&str>,
+) -> Cow<'r, str> {
+    let val = match trim {
+        true => record[idx].trim(),
+        false => &record[idx],
+    };
+    if let (true, Some(na)) = (val.is_empty(), na) {
+        return Cow::Owned(na.to_owned());
+    }
+    match hash_pos.contains(&idx) {
+        true => Cow::Owned(short_hash(val)),
+        false => Cow::Borrowed(val),
+    }
+}
+
+/// Joins up `fields` with `delimiter`, quoting them where needed, the way kat writes a record.
+fn join_fields<T: AsRef<[u8]>>(fields: impl IntoIterator<Item = T>, delimiter: u8) -> String {
+    let mut csv_writer = csv::WriterBuilder::new()
+        .delimiter(delimiter)
+        .terminator(csv::Terminator::Any(b'\n'))
+        .from_writer(vec![]);
+    // Writing to a Vec can't fail
+    csv_writer.write_record(fields).unwrap();
+    let mut joined = String::from_utf8(csv_writer.into_inner().unwrap()).unwrap();
+    joined.pop();
+    joined
+}
+
+/// Cuts a single line, which shouldn't include its line terminator, just as kat would cut a line
+/// of its input given `selector` and `delimiter` but no other options. So a line without the
+/// delimiter in it comes back as it is, while any line which isn't a JSON object cuts down to
+/// nothing with `Selector::Keys`. With `Selector::Names` or `Selector::NamesMatching` the line is
+/// its own header, and cuts down to nothing if none of its fields are selected.
+pub fn cut_line(line: &str, selector: &Selector, delimiter: u8) -> String {
+    // With nothing but a selector to check, there's nothing for the builder to object to
+    let mut config = ConfigBuilder::new()
+        .selector(selector.clone())
+        .build()
+        .unwrap();
+    config.delimiter = delimiter;
+    config.output_delimiter = delimiter;
+    // Bound so that the lines are done with before the config they borrow
+    let cut = select_lines(line.as_bytes(), &config)
+        .next()
+        .and_then(Result::ok)
+        .unwrap_or_default();
+    cut
+}
+
+/// What we end each output line with, whatever the input lines ended with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Terminator {
+    Lf,
+    CrLf,
+    Nul,
+}
+
+impl Terminator {
+    fn as_bytes(&self) -> &'static [u8] {
+        match self {
+            Terminator::Lf => b"\n",
+            Terminator::CrLf => b"\r\n",
+            Terminator::Nul => b"\0",
+        }
+    }
+
+    fn for_csv(&self) -> csv::Terminator {
+        match self {
+            Terminator::Lf => csv::Terminator::Any(b'\n'),
+            Terminator::CrLf => csv::Terminator::CRLF,
+            Terminator::Nul => csv::Terminator::Any(b'\0'),
+        }
+    }
+}
+
+/// A format the input can be compressed in, each needing the feature of the same name.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Compression {
+    Gzip,
+    Zstd,
+    Bzip2,
+}
+
+impl Compression {
+    /// The format a FILE is compressed in going by its extension, if it's one we'd know.
+    fn of_file(file: &str) -> Option<Compression> {
+        [
+            (".gz", Compression::Gzip),
+            (".zst", Compression::Zstd),
+            (".bz2", Compression::Bzip2),
+        ]
+        .into_iter()
+        .find(|(extension, _)| file.ends_with(extension))
+        .map(|(_, compression)| compression)
+    }
+
+    /// The feature kat has to be built with to decompress the format.
+    fn feature(&self) -> &'static str {
+        match self {
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+            Compression::Bzip2 => "bzip2",
+        }
+    }
+
+    fn built_in(&self) -> bool {
+        match self {
+            Compression::Gzip => cfg!(feature = "gzip"),
+            Compression::Zstd => cfg!(feature = "zstd"),
+            Compression::Bzip2 => cfg!(feature = "bzip2"),
+        }
+    }
+}
+
+/// Which of the fields written out are quoted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Quoting {
+    /// Those the csv writer sees a need to, which depends on the line terminator: with NUL, a
+    /// field with a CR or LF in it goes unquoted
+    Necessary,
+    /// Those RFC 4180 says to, i.e. any with a delimiter, quote, CR or LF in it, so that they read
+    /// back as they were whatever the line terminator
+    Strict,
+    Always,
+    Never,
+}
+
+/// How the selected fields of each record are written out.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OutputFormat {
+    Delimited,
+    /// A JSON object per record, keyed by (1-based) field number
+    Json,
+}
+
+/// One key of a `--sort-by` spec such as `2nr`, i.e. sort on field 2, numerically, descending.
+#[derive(Debug, PartialEq)]
+struct SortKey {
+    field: usize,
+    numeric: bool,
+    reverse: bool,
+}
+
+impl SortKey {
+    fn compare(&self, a: &StringRecord, b: &StringRecord) -> Ordering {
+        let a = a.get(self.field).unwrap_or("");
+        let b = b.get(self.field).unwrap_or("");
+        let ordering = if self.numeric {
+            // Values which aren't numbers sort before all the ones which are
+            let parse = |s: &str| s.trim().parse::<f64>().ok();
+            match (parse(a), parse(b)) {
+                (Some(x), Some(y)) => x.total_cmp(&y),
+                (x, y) => x.is_some().cmp(&y.is_some()),
+            }
+        } else {
+            a.cmp(b)
+        };
+        if self.reverse {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    }
+}
+
+/// The type inferred for a column when describing a file. The variants are ordered from the most
+/// to the least specific so that a column only ever widens as more values are seen.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ColumnType {
+    Bool,
+    Int,
+    Float,
+    Str,
+}
+
+impl ColumnType {
+    fn of(value: &str) -> ColumnType {
+        if value.eq_ignore_ascii_case("true") || value.eq_ignore_ascii_case("false") {
+            ColumnType::Bool
+        } else if value.parse::<i64>().is_ok() {
+            ColumnType::Int
+        } else if value.parse::<f64>().is_ok() {
+            ColumnType::Float
+        } else {
+            ColumnType::Str
+        }
+    }
+
+    fn widen(self, other: ColumnType) -> ColumnType {
+        match (self, other) {
+            (a, b) if a == b => a,
+            (ColumnType::Int, ColumnType::Float) | (ColumnType::Float, ColumnType::Int) => {
+                ColumnType::Float
+            }
+            _ => ColumnType::Str,
+        }
+    }
+}
+
+impl std::fmt::Display for ColumnType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ColumnType::Bool => "bool",
+            ColumnType::Int => "int",
+            ColumnType::Float => "float",
+            ColumnType::Str => "string",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// What went wrong, with the message kat reports it with.
+#[derive(Debug, thiserror::Error)]
+pub enum KatError {
+    #[error("kat: bad delimiter")]
+    BadDelimiter,
+    #[error("kat: bad output delimiter")]
+    BadOutputDelimiter,
+    /// A list of positions (or part of one) which isn't a position at all
+    #[error("kat: illegal list value: '{0}'")]
+    IllegalListValue(String),
+    #[error("kat: list values may not include zero")]
+    ZeroInList,
+    /// A range ending before it starts, with its ends as they were listed
+    #[error("First number in range ({start}) must be lower than second number ({end})")]
+    EmptyRange { start: usize, end: usize },
+    /// A line which --strict-utf8 stopped us from cutting into invalid UTF-8, `offset` being
+    /// where the bytes we cut out stop being valid
+    #[error("line {line_num} cuts down to invalid UTF-8 at byte {}", .offset + 1)]
+    CutInvalidUtf8 { line_num: usize, offset: usize },
+    /// A line too short for one of the positions asked for, which --strict-ranges won't let pass.
+    /// The `unit` is what the line is made up of, be it fields, characters or bytes, and the
+    /// `position` is as it was listed.
+    #[error(
+        "line {line_num} has {len} {unit}{}, too few for {unit} {position}",
+        if *.len == 1 { "" } else { "s" }
+    )]
+    OutOfRange {
+        line_num: usize,
+        len: usize,
+        unit: &'static str,
+        position: String,
+    },
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Csv(#[from] csv::Error),
+    #[error(transparent)]
+    InvalidUtf8(#[from] std::string::FromUtf8Error),
+    /// Anything else
+    #[error("{0}")]
+    Other(String),
+}
+
+impl From<String> for KatError {
+    fn from(msg: String) -> Self {
+        KatError::Other(msg)
+    }
+}
+
+impl From<&str> for KatError {
+    fn from(msg: &str) -> Self {
+        KatError::Other(msg.to_owned())
+    }
+}
+
+pub type CliResult<T> = Result<T, KatError>;
+
+pub fn run(config: Config) -> CliResult<()> {
+    let result = cut_to_output(&config);
+    // However far we got, which is all there is to go on when something went wrong
+    if let Some(stats) = &config.stats {
+        stats.report();
+    }
+    if let Some(progress) = &config.progress {
+        progress.finish();
+    }
+    result
 }
 
-type Positions = Vec<Range<usize>>;
+fn cut_to_output(config: &Config) -> CliResult<()> {
+    if config.show_ranges {
+        writeln!(stdout(), "{}", show_ranges(&config.selector))?;
+        return Ok(());
+    }
+    if config.detect_encoding {
+        return detect_encodings(config);
+    }
+    if let Some(cmd) = &config.pipe_to {
+        return pipe_to(cmd, config);
+    }
+    if let Some((prefix, parts)) = &config.split_output {
+        return split_output(prefix, *parts, config);
+    }
+    if let Some(path) = &config.output {
+        let file =
+            File::create(path).map_err(|e| format!("kat: can't write to {}: {}", path, e))?;
+        return write_buffered(config, file);
+    }
+    // Stdout is line buffered which, flushing on every line, is slow going on big files
+    write_buffered(config, stdout().lock())
+}
+
+fn write_buffered(config: &Config, out: impl Write) -> CliResult<()> {
+    let mut out = BufWriter::new(out);
+    write_output(config, &mut out)?;
+    out.flush()?;
+    Ok(())
+}
+
+fn write_output(config: &Config, out: &mut impl Write) -> CliResult<()> {
+    match &config.stats {
+        Some(stats) => {
+            let terminator = *config.terminator.as_bytes().last().unwrap();
+            let mut counted = CountingWriter {
+                inner: out,
+                lines: LineCounter::new(terminator),
+                stats: stats.clone(),
+            };
+            write_capped(config, &mut counted)
+        }
+        None => write_capped(config, out),
+    }
+}
+
+fn write_capped(config: &Config, out: &mut impl Write) -> CliResult<()> {
+    if config.write_bom {
+        out.write_all(b"\xEF\xBB\xBF")?;
+    }
+    let cap = match config.max_output_bytes {
+        Some(cap) => cap,
+        None => return cut_files(config, out),
+    };
+    let mut capped = Capped {
+        inner: out,
+        remaining: cap,
+        whole_lines: config.whole_lines,
+        terminator: *config.terminator.as_bytes().last().unwrap(),
+        line: Vec::new(),
+    };
+    let result = cut_files(config, &mut capped).and_then(|_| Ok(capped.finish()?));
+    match result {
+        Err(e) if reached_output_cap(&e) => {
+            eprintln!("kat: stopped at the output limit of {} bytes", cap);
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+fn cut_files(config: &Config, out: &mut impl Write) -> CliResult<()> {
+    // Like cut and cat, we carry on past any file we can't open but still fail in the end, so each
+    // way of cutting hands back the number of files it couldn't open
+    let failed = if config.bytes_total {
+        count_bytes(config, out)?
+    } else if config.interleave {
+        interleave(config, out)?
+    } else if config.paste {
+        paste(config, out)?
+    } else if config.follow {
+        follow(&config.files[0], config, out)?;
+        0
+    } else if config.jobs > 1 && !config.files.iter().any(|file| file == "-") {
+        // Stdin can only be read from the one thread
+        cut_in_parallel(config, out)?
+    } else {
+        let mut failed = 0;
+        // Stdin is read when its turn comes like any other file, so it can go between two
+        for (idx, file) in config.files.iter().enumerate() {
+            match open(file.as_str(), config) {
+                Err(e) => {
+                    eprintln!("{}: {}", file, e);
+                    failed += 1;
+                }
+                Ok(reader) => {
+                    write_banner(file, idx, config, out)?;
+                    process_reader(reader, config, out).map_err(|e| in_file(e, file))?
+                }
+            }
+        }
+        failed
+    };
+    match failed {
+        0 => Ok(()),
+        _ => Err(format!(
+            "kat: {} of {} files could not be read",
+            failed,
+            config.files.len()
+        )
+        .into()),
+    }
+}
+
+/// Cuts the files on up to `config.jobs` threads at once. Each file is cut into a buffer of its
+/// own, so the output comes out in the same order as cutting the files one after the other would,
+/// as do the errors for files we can't open.
+fn cut_in_parallel(config: &Config, out: &mut impl Write) -> CliResult<usize> {
+    let files = &config.files;
+    let next_file = AtomicUsize::new(0);
+    // The first file to fail partway through, as there's no need to cut any after it
+    let failed_at = AtomicUsize::new(usize::MAX);
+    let (sender, receiver) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..config.jobs.min(files.len()) {
+            let sender = sender.clone();
+            let (next_file, failed_at) = (&next_file, &failed_at);
+            scope.spawn(move || loop {
+                let idx = next_file.fetch_add(1, AtomicOrdering::SeqCst);
+                if idx >= files.len() || idx > failed_at.load(AtomicOrdering::SeqCst) {
+                    return;
+                }
+                let mut cut = Vec::new();
+                let outcome = match open(files[idx].as_str(), config) {
+                    Err(e) => Outcome::Unopened(e.to_string()),
+                    Ok(reader) => match process_reader(reader, config, &mut cut) {
+                        Ok(()) => Outcome::Done,
+                        Err(e) => {
+                            failed_at.fetch_min(idx, AtomicOrdering::SeqCst);
+                            Outcome::Failed(in_file(e, &files[idx]).to_string())
+                        }
+                    },
+                };
+                if sender.send((idx, cut, outcome)).is_err() {
+                    return;
+                }
+            });
+        }
+        // Only the threads' senders are left, so the receiver finishes once they've all finished
+        drop(sender);
+
+        let mut failed = 0;
+        let mut next_out = 0;
+        let mut done = HashMap::new();
+        for (idx, cut, outcome) in receiver {
+            done.insert(idx, (cut, outcome));
+            while let Some((cut, outcome)) = done.remove(&next_out) {
+                if !matches!(outcome, Outcome::Unopened(_)) {
+                    write_banner(&files[next_out], next_out, config, out)?;
+                }
+                // Whatever was cut before a failure is written out, just as it would have been
+                out.write_all(&cut)?;
+                match outcome {
+                    Outcome::Done => {}
+                    Outcome::Unopened(msg) => {
+                        eprintln!("{}: {}", files[next_out], msg);
+                        failed += 1;
+                    }
+                    Outcome::Failed(msg) => return Err(msg.into()),
+                }
+                next_out += 1;
+            }
+        }
+        Ok(failed)
+    })
+}
+
+/// Heads what's cut from the `idx`th file with its name under --with-filename, like head does
+/// when given many files, with a blank line between it and the file before. Stdin has no name
+/// to give it.
+fn write_banner(file: &str, idx: usize, config: &Config, out: &mut impl Write) -> io::Result<()> {
+    if !config.with_filename || file == "-" {
+        return Ok(());
+    }
+    let terminator = config.terminator.as_bytes();
+    if idx > 0 {
+        out.write_all(terminator)?;
+    }
+    write!(out, "==> {} <==", file)?;
+    out.write_all(terminator)
+}
+
+/// How cutting a file on another thread went. Errors aren't Send, so only their messages are
+/// passed back.
+enum Outcome {
+    Done,
+    Unopened(String),
+    Failed(String),
+}
+
+/// Like `tail -f`, keeps cutting the lines appended to `file` until we're interrupted.
+fn follow(file: &str, config: &Config, out: &mut impl Write) -> CliResult<()> {
+    let handle = open_retrying(config.retry_on_lock, || File::open(file))
+        .map_err(|e| format!("{}: {}", file, e))?;
+    if !handle.metadata()?.is_file() {
+        return Err(format!("kat: can't follow '{}' as it isn't a regular file", file).into());
+    }
+    let reader = Follow {
+        inner: handle,
+        poll: Duration::from_millis(200),
+        idle_limit: None,
+    };
+    process_reader(BufReader::new(reader), config, out)
+}
+
+/// A reader which treats EOF as a cue to wait for more data to be appended. It only gives up and
+/// reports EOF after `idle_limit` consecutive polls (if there's a limit at all) turn up nothing.
+struct Follow<R> {
+    inner: R,
+    poll: Duration,
+    idle_limit: Option<u32>,
+}
+
+impl<R: Read> Read for Follow<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut idle = 0;
+        loop {
+            let n = self.inner.read(buf)?;
+            if n > 0 || buf.is_empty() || self.idle_limit.is_some_and(|limit| idle >= limit) {
+                return Ok(n);
+            }
+            idle += 1;
+            thread::sleep(self.poll);
+        }
+    }
+}
+
+/// What went in and came out over the whole run, for --stats.
+#[derive(Debug, Default)]
+struct Stats {
+    lines_read: AtomicUsize,
+    lines_written: AtomicUsize,
+    /// The lines dropped along the way, as with --only-delimited, counted as they're dropped
+    lines_skipped: AtomicUsize,
+    bytes_written: AtomicUsize,
+}
+
+impl ReadTally for Stats {
+    fn add_read(&self, lines: usize, _bytes: usize) {
+        self.lines_read.fetch_add(lines, AtomicOrdering::Relaxed);
+    }
+}
 
-pub type CliResult<T> = Result<T, Box<dyn Error>>;
+impl Stats {
+    /// Writes the counts out to stderr, where they can't get mixed up with the output.
+    fn report(&self) {
+        let lines_read = self.lines_read.load(AtomicOrdering::Relaxed);
+        eprintln!("lines read: {}", lines_read);
+        eprintln!(
+            "lines written: {}",
+            self.lines_written.load(AtomicOrdering::Relaxed)
+        );
+        eprintln!(
+            "lines skipped: {}",
+            self.lines_skipped.load(AtomicOrdering::Relaxed)
+        );
+        eprintln!(
+            "bytes written: {}",
+            self.bytes_written.load(AtomicOrdering::Relaxed)
+        );
+    }
+}
+
+/// Counts a line as skipped for --stats, if it's on.
+fn count_skipped(config: &Config) {
+    if let Some(stats) = &config.stats {
+        stats.lines_skipped.fetch_add(1, AtomicOrdering::Relaxed);
+    }
+}
+
+/// How long --progress waits at least before showing the counts again.
+const PROGRESS_EVERY: Duration = Duration::from_millis(200);
+
+/// The clock is only looked at whenever another this many bytes have been read.
+const PROGRESS_CHUNK: usize = 64 * 1024;
+
+/// The lines and bytes read so far, for --progress, which are shown on `out` (stderr, unless
+/// it's a test) every so often as they go up. Those of all the FILEs are added up, as is their
+/// size if it's known beforehand.
+struct Progress {
+    lines: AtomicUsize,
+    bytes: AtomicUsize,
+    total_bytes: Option<u64>,
+    shown: Mutex<(Instant, Box<dyn Write + Send>)>,
+}
+
+impl std::fmt::Debug for Progress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Progress")
+            .field("lines", &self.lines)
+            .field("bytes", &self.bytes)
+            .field("total_bytes", &self.total_bytes)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ReadTally for Progress {
+    fn add_read(&self, lines: usize, bytes: usize) {
+        self.lines.fetch_add(lines, AtomicOrdering::Relaxed);
+        let before = self.bytes.fetch_add(bytes, AtomicOrdering::Relaxed);
+        if before / PROGRESS_CHUNK == (before + bytes) / PROGRESS_CHUNK {
+            return;
+        }
+        // Another thread showing the counts will do for this one too
+        if let Ok(mut shown) = self.shown.try_lock() {
+            if shown.0.elapsed() >= PROGRESS_EVERY {
+                shown.0 = Instant::now();
+                let _ = self.show(&mut shown.1);
+            }
+        }
+    }
+}
+
+impl Progress {
+    fn new(total_bytes: Option<u64>, out: Box<dyn Write + Send>) -> Self {
+        Progress {
+            lines: AtomicUsize::new(0),
+            bytes: AtomicUsize::new(0),
+            total_bytes,
+            shown: Mutex::new((Instant::now(), out)),
+        }
+    }
+
+    /// Writes the counts over the last ones shown.
+    fn show(&self, out: &mut dyn Write) -> io::Result<()> {
+        write!(
+            out,
+            "\rkat: {} lines",
+            self.lines.load(AtomicOrdering::Relaxed)
+        )?;
+        let bytes = self.bytes.load(AtomicOrdering::Relaxed);
+        match self.total_bytes {
+            Some(total) => write!(out, ", {} of {} bytes", bytes, total)?,
+            None => write!(out, ", {} bytes", bytes)?,
+        }
+        out.flush()
+    }
+
+    /// Shows the final counts, leaving them on a line of their own.
+    fn finish(&self) {
+        if let Ok(mut shown) = self.shown.lock() {
+            let _ = self.show(&mut shown.1).and_then(|_| writeln!(shown.1));
+        }
+    }
+}
+
+/// Somewhere to add up what's read through a `CountingReader`.
+trait ReadTally: Send + Sync {
+    fn add_read(&self, lines: usize, bytes: usize);
+}
+
+/// Counts lines as they go by, each one as soon as its first byte does so that a last line
+/// with no terminator counts too.
+struct LineCounter {
+    terminator: u8,
+    at_line_start: bool,
+}
+
+impl LineCounter {
+    fn new(terminator: u8) -> Self {
+        LineCounter {
+            terminator,
+            at_line_start: true,
+        }
+    }
+
+    /// The number of lines which start in `buf`.
+    fn count(&mut self, buf: &[u8]) -> usize {
+        let mut lines = 0;
+        for &b in buf {
+            lines += usize::from(self.at_line_start);
+            self.at_line_start = b == self.terminator;
+        }
+        lines
+    }
+}
+
+/// A reader which adds the lines and bytes read through it to `tally`. They're counted as they're
+/// consumed rather than when they're buffered, as kat doesn't always read all it buffers.
+struct CountingReader<R> {
+    inner: R,
+    lines: LineCounter,
+    tally: Arc<dyn ReadTally>,
+}
+
+impl<R: BufRead> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = {
+            let available = self.fill_buf()?;
+            let len = available.len().min(buf.len());
+            buf[..len].copy_from_slice(&available[..len]);
+            len
+        };
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl<R: BufRead> BufRead for CountingReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        // What's being consumed is still at the front of the buffer, which won't be refilled
+        // (or block on a read) as long as there's anything in it
+        if amt > 0 {
+            if let Ok(buf) = self.inner.fill_buf() {
+                let consumed = &buf[..amt.min(buf.len())];
+                self.tally
+                    .add_read(self.lines.count(consumed), consumed.len());
+            }
+        }
+        self.inner.consume(amt);
+    }
+}
+
+/// A writer which adds the lines and bytes written through it to `stats`.
+struct CountingWriter<W> {
+    inner: W,
+    lines: LineCounter,
+    stats: Arc<Stats>,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        let lines = self.lines.count(&buf[..len]);
+        self.stats
+            .lines_written
+            .fetch_add(lines, AtomicOrdering::Relaxed);
+        self.stats
+            .bytes_written
+            .fetch_add(len, AtomicOrdering::Relaxed);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// A writer which passes through no more than `remaining` bytes, failing with `OutputCapReached`
+/// once it's full. With `whole_lines` it would rather drop a line entirely than write part of it,
+/// going by the last byte of the terminator the lines are written out with.
+struct Capped<W> {
+    inner: W,
+    remaining: usize,
+    whole_lines: bool,
+    terminator: u8,
+    line: Vec<u8>,
+}
+
+#[derive(Debug)]
+struct OutputCapReached;
+
+impl std::fmt::Display for OutputCapReached {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "kat: reached the output limit")
+    }
+}
+
+impl Error for OutputCapReached {}
+
+impl<W: Write> Capped<W> {
+    fn pass(&mut self, buf: &[u8]) -> io::Result<()> {
+        if buf.len() > self.remaining {
+            if !self.whole_lines {
+                self.inner.write_all(&buf[..self.remaining])?;
+            }
+            self.remaining = 0;
+            return Err(io::Error::other(OutputCapReached));
+        }
+        self.remaining -= buf.len();
+        self.inner.write_all(buf)
+    }
+
+    /// Writes out any trailing partial line.
+    fn finish(&mut self) -> io::Result<()> {
+        let line = std::mem::take(&mut self.line);
+        self.pass(&line)
+    }
+}
+
+impl<W: Write> Write for Capped<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.whole_lines {
+            self.pass(buf)?;
+            return Ok(buf.len());
+        }
+        let terminator = self.terminator;
+        for chunk in buf.split_inclusive(|&b| b == terminator) {
+            self.line.extend_from_slice(chunk);
+            if chunk.ends_with(&[terminator]) {
+                let line = std::mem::take(&mut self.line);
+                self.pass(&line)?;
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Under --strict-ranges, checks that line `line_num`, which is `len` units long, has room for
+/// everything in `selection`.
+fn check_range(
+    selection: &Selection,
+    len: usize,
+    line_num: usize,
+    unit: &'static str,
+    config: &Config,
+) -> Result<(), KatError> {
+    let first = usize::from(!config.zero_based);
+    // How long a line has to be for each position to be in it, and the position as it was listed
+    let longest = selection
+        .positions
+        .iter()
+        .map(|r| match from_end(r.start) {
+            Some(back) => (back, format!("-{}", back)),
+            None if r.end == OPEN_END => (r.start + 1, (r.start + first).to_string()),
+            None => (r.end, (r.end - 1 + first).to_string()),
+        })
+        .max_by_key(|(min_len, _)| *min_len);
+    match longest {
+        Some((min_len, position)) if config.strict_ranges && len < min_len => {
+            Err(KatError::OutOfRange {
+                line_num,
+                len,
+                unit,
+                position,
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Adds the name of the file to errors which say which line of it went wrong but not which file.
+fn in_file(err: KatError, file: &str) -> KatError {
+    match err {
+        KatError::CutInvalidUtf8 { .. } | KatError::OutOfRange { .. } => {
+            format!("kat: {}: {}", file, err).into()
+        }
+        err => err,
+    }
+}
+
+/// The io error behind `err`, which might reach us directly or wrapped up by the csv writer.
+fn io_error(err: &KatError) -> Option<&io::Error> {
+    match err {
+        KatError::Csv(e) => match e.kind() {
+            csv::ErrorKind::Io(e) => Some(e),
+            _ => None,
+        },
+        KatError::Io(e) => Some(e),
+        _ => None,
+    }
+}
+
+fn reached_output_cap(err: &KatError) -> bool {
+    io_error(err)
+        .and_then(|e| e.get_ref())
+        .is_some_and(|inner| inner.is::<OutputCapReached>())
+}
+
+/// Whether we failed because whatever was reading our output went away, as when piping into
+/// `head`, which is no reason to complain.
+pub fn is_broken_pipe(err: &KatError) -> bool {
+    io_error(err).is_some_and(|e| e.kind() == io::ErrorKind::BrokenPipe)
+}
+
+/// Runs `cmd` through the shell and writes all our output to its stdin instead of our stdout.
+fn pipe_to(cmd: &str, config: &Config) -> CliResult<()> {
+    let (shell, flag) = if cfg!(windows) {
+        ("cmd", "/C")
+    } else {
+        ("sh", "-c")
+    };
+    let mut child = Command::new(shell)
+        .args([flag, cmd])
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("kat: failed to run '{}': {}", cmd, e))?;
+
+    // The child only sees EOF once its stdin is dropped, so that must happen before we wait on it
+    let result = write_output(config, &mut child.stdin.take().unwrap());
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(format!("kat: '{}' failed with {}", cmd, status).into());
+    }
+    result
+}
+
+/// Like `wc -c`, prints the number of bytes in each file followed by the total if there are many.
+fn count_bytes(config: &Config, out: &mut impl Write) -> CliResult<usize> {
+    let mut total = 0;
+    let mut failed = 0;
+    for file in &config.files {
+        match open(file.as_str(), config) {
+            Err(e) => {
+                eprintln!("{}: {}", file, e);
+                failed += 1;
+            }
+            Ok(mut reader) => {
+                let count = io::copy(&mut reader, &mut io::sink())?;
+                total += count;
+                writeln!(out, "{} {}", count, file)?;
+            }
+        }
+    }
+    if config.files.len() > 1 {
+        writeln!(out, "{} total", total)?;
+    }
+    Ok(failed)
+}
+
+/// What `selector` picks out of each line, with positions given as the 0-based, half-open
+/// ranges of indices they were parsed into.
+fn show_ranges(selector: &Selector) -> String {
+    let show = |positions: &Positions| {
+        positions
+            .iter()
+            .map(|r| match from_end(r.start) {
+                Some(back) => format!("-{}", back),
+                None if r.end == OPEN_END => format!("{}..", r.start),
+                None => format!("{}..{}", r.start, r.end),
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    match selector {
+        Selector::Bytes(positions) => format!("bytes: {}", show(positions)),
+        Selector::Chars(positions) => format!("characters: {}", show(positions)),
+        Selector::Fields(positions) => format!("fields: {}", show(positions)),
+        Selector::Keys(keys) => format!("keys: {}", keys.join(", ")),
+        Selector::Names(names) => format!("names: {}", names.join(", ")),
+        Selector::NamesMatching(regex) => format!("names matching: {}", regex),
+    }
+}
+
+/// Reports the likely encoding of each file on stderr without processing any of them.
+fn detect_encodings(config: &Config) -> CliResult<()> {
+    // This is plenty for the detector to make up its mind without reading huge files in full
+    const SNIFF_LEN: u64 = 64 * 1024;
+    for file in &config.files {
+        let mut head = Vec::new();
+        match open(file.as_str(), config) {
+            Err(e) => eprintln!("{}: {}", file, e),
+            Ok(reader) => {
+                reader.take(SNIFF_LEN).read_to_end(&mut head)?;
+                eprintln!("{}: {}", file, sniff_encoding(&head)?);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A BOM settles the question outright, otherwise we go with chardetng's best guess (which never
+/// considers UTF-16 since it's only interested in ASCII compatible encodings).
+#[cfg(feature = "encoding")]
+fn sniff_encoding(head: &[u8]) -> CliResult<&'static str> {
+    if let Some((encoding, _)) = encoding_rs::Encoding::for_bom(head) {
+        return Ok(encoding.name());
+    }
+    let mut detector = chardetng::EncodingDetector::new();
+    detector.feed(head, true);
+    Ok(detector.guess(None, true).name())
+}
+
+#[cfg(not(feature = "encoding"))]
+fn sniff_encoding(_head: &[u8]) -> CliResult<&'static str> {
+    Err("kat: --detect-encoding requires kat to be built with the 'encoding' feature".into())
+}
+
+/// Deals our output out line by line across `PREFIX.0` to `PREFIX.(parts - 1)`.
+fn split_output(prefix: &str, parts: usize, config: &Config) -> CliResult<()> {
+    let mut writers = Vec::with_capacity(parts);
+    for part in 0..parts {
+        let path = format!("{}.{}", prefix, part);
+        let file = File::create(&path).map_err(|e| format!("{}: {}", path, e))?;
+        writers.push(BufWriter::new(file));
+    }
+    let mut out = RoundRobin {
+        writers,
+        next: 0,
+        terminator: *config.terminator.as_bytes().last().unwrap(),
+        line: Vec::new(),
+    };
+    write_output(config, &mut out)?;
+    out.finish()?;
+    Ok(())
+}
+
+/// A writer which forwards each complete line, as ended by `terminator`, to the next of its
+/// writers in turn. Note that a quoted field with an embedded terminator will see its record
+/// split across two writers.
+struct RoundRobin<W: Write> {
+    writers: Vec<W>,
+    next: usize,
+    terminator: u8,
+    line: Vec<u8>,
+}
+
+impl<W: Write> RoundRobin<W> {
+    /// Writes out any trailing partial line and flushes all the writers.
+    fn finish(mut self) -> io::Result<()> {
+        if !self.line.is_empty() {
+            self.writers[self.next].write_all(&self.line)?;
+        }
+        self.flush()
+    }
+}
+
+impl<W: Write> Write for RoundRobin<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let terminator = self.terminator;
+        for chunk in buf.split_inclusive(|&b| b == terminator) {
+            self.line.extend_from_slice(chunk);
+            if chunk.ends_with(&[terminator]) {
+                self.writers[self.next].write_all(&self.line)?;
+                self.line.clear();
+                self.next = (self.next + 1) % self.writers.len();
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writers.iter_mut().try_for_each(|w| w.flush())
+    }
+}
+
+/// Opens all the given files up front, reporting (and skipping) the ones which can't be opened,
+/// and hands back those which could be along with a count of those which couldn't.
+fn open_all(config: &Config) -> (Vec<Box<dyn BufRead>>, usize) {
+    let mut readers = Vec::new();
+    let mut failed = 0;
+    for file in &config.files {
+        match open(file.as_str(), config) {
+            Err(e) => {
+                eprintln!("{}: {}", file, e);
+                failed += 1;
+            }
+            Ok(reader) => readers.push(reader),
+        }
+    }
+    (readers, failed)
+}
+
+/// Reads the files in lock-step, cutting line N of every file before moving on to line N + 1.
+/// Files which run out of lines early simply drop out of the rotation.
+fn interleave(config: &Config, out: &mut impl Write) -> CliResult<usize> {
+    let (mut readers, failed) = open_all(config);
+    let separator = if config.zero_terminated { b'\0' } else { b'\n' };
+    let mut line = Vec::new();
+    while !readers.is_empty() {
+        let mut live = Vec::with_capacity(readers.len());
+        for mut reader in readers {
+            line.clear();
+            if reader.read_until(separator, &mut line)? > 0 {
+                process_reader(line.as_slice(), config, out)?;
+                live.push(reader);
+            }
+        }
+        readers = live;
+    }
+    Ok(failed)
+}
+
+/// Like `paste`, cuts line N of every file and joins the results on a single output line with the
+/// output delimiter. Files which run out of lines early contribute an empty value until all the
+/// files are exhausted.
+fn paste(config: &Config, out: &mut impl Write) -> CliResult<usize> {
+    let (readers, failed) = open_all(config);
+    let mut readers = readers.into_iter().map(Some).collect::<Vec<_>>();
+    let separator = if config.zero_terminated { b'\0' } else { b'\n' };
+    // The pieces are put together as the fields (or regions) of a line would be
+    let output_delimiter = match (&config.joiner, &config.join) {
+        (Some(joiner), _) => joiner.as_bytes(),
+        (None, Some(join)) => join.as_bytes(),
+        (None, None) => std::slice::from_ref(&config.output_delimiter),
+    };
+    let mut line = Vec::new();
+    loop {
+        let mut pieces = Vec::with_capacity(readers.len());
+        let mut any_line = false;
+        for slot in readers.iter_mut() {
+            let mut piece = Vec::new();
+            if let Some(reader) = slot {
+                line.clear();
+                if reader.read_until(separator, &mut line)? > 0 {
+                    process_reader(line.as_slice(), config, &mut piece)?;
+                    any_line = true;
+                } else {
+                    *slot = None;
+                }
+            }
+            if piece.ends_with(config.terminator.as_bytes()) {
+                piece.truncate(piece.len() - config.terminator.as_bytes().len());
+            }
+            pieces.push(piece);
+        }
+        if !any_line {
+            return Ok(failed);
+        }
+        out.write_all(&pieces.join(output_delimiter))?;
+        out.write_all(config.terminator.as_bytes())?;
+    }
+}
+
+/// Splits the input up into lines without their terminators, which are either `\n` (or `\r\n`
+/// unless we're to `keep_cr`) or NUL with `--zero-terminated`.
+fn raw_lines(
+    reader: impl BufRead,
+    zero_terminated: bool,
+    keep_cr: bool,
+) -> impl Iterator<Item = io::Result<Vec<u8>>> {
+    let separator = if zero_terminated { b'\0' } else { b'\n' };
+    reader.split(separator).map(move |line| {
+        let mut line = line?;
+        if !zero_terminated && !keep_cr && line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        Ok(line)
+    })
+}
+
+/// Like `raw_lines` but for lines which have to be valid UTF-8.
+fn text_lines(
+    reader: impl BufRead,
+    zero_terminated: bool,
+    keep_cr: bool,
+) -> impl Iterator<Item = CliResult<String>> {
+    raw_lines(reader, zero_terminated, keep_cr).map(|line| {
+        String::from_utf8(line?).map_err(|_| "stream did not contain valid UTF-8".into())
+    })
+}
+
+/// Gives a record we split up ourselves the position of the `idx`th line, as the csv reader would.
+fn at_line(mut record: StringRecord, idx: usize) -> StringRecord {
+    let mut position = csv::Position::new();
+    position.set_line(idx as u64 + 1).set_record(idx as u64);
+    record.set_position(Some(position));
+    record
+}
+
+/// Pairs each of `lines` with its number, which counts from the top of the file whatever
+/// --skip-lines skipped.
+fn with_line_numbers<T>(
+    lines: impl Iterator<Item = T>,
+    config: &Config,
+) -> impl Iterator<Item = (usize, T)> {
+    let first = config.skip_lines + 1;
+    lines
+        .enumerate()
+        .map(move |(idx, line)| (first + idx, line))
+}
+
+/// Prefixes `line` with its line number under --line-numbers.
+fn numbered(line_num: usize, line: String, config: &Config) -> String {
+    match (config.line_numbers, &config.joiner) {
+        (true, Some(joiner)) => format!("{}{}{}", line_num, joiner, line),
+        // The output delimiter is a single ASCII byte
+        (true, None) => format!(
+            "{}{}{}",
+            line_num,
+            char::from(config.output_delimiter),
+            line
+        ),
+        (false, _) => line,
+    }
+}
+
+/// Cuts each line read from `reader` the way kat would, handing back the cut lines one at a time
+/// (without their terminators) rather than writing them out. Anything which needs all of the input
+/// before it can write a thing, like --describe or --group-sum, is left to `run`.
+pub fn select_lines<'a, R: BufRead + 'a>(
+    reader: R,
+    config: &'a Config,
+) -> impl Iterator<Item = CliResult<String>> + 'a {
+    let lines: Box<dyn Iterator<Item = CliResult<String>> + 'a> = match &config.selector {
+        Selector::Keys(_) if cfg!(not(feature = "ndjson")) => Box::new(std::iter::once(Err(
+            "kat: --from-ndjson requires kat to be built with the 'ndjson' feature".into(),
+        ))),
+        Selector::Keys(keys) => Box::new(key_lines(reader, keys, config)),
+        Selector::Bytes(positions) => {
+            let selection = Selection::new(positions, config.keep_order);
+            let lines = raw_lines(reader, config.zero_terminated, config.keep_cr);
+            let mut byte_buf = Vec::new();
+            let lines = with_line_numbers(lines, config).map(move |(line_num, result)| {
+                let mut cut = Vec::new();
+                let kept = cut_line_bytes(
+                    &result?,
+                    line_num,
+                    &selection,
+                    config,
+                    &mut byte_buf,
+                    &mut cut,
+                )?;
+                // What's written out is a str, so it's bound to be UTF-8
+                Ok(kept.then(|| String::from_utf8(cut).unwrap()))
+            });
+            Box::new(lines.filter_map(CliResult::transpose))
+        }
+        Selector::Chars(positions) => {
+            let selection = Selection::new(positions, config.keep_order);
+            let lines = raw_lines(reader, config.zero_terminated, config.keep_cr);
+            let lines = with_line_numbers(lines, config).map(move |(line_num, result)| {
+                match String::from_utf8(result?) {
+                    Ok(line) => Ok((line_num, line)),
+                    Err(e) if config.pretty_errors => {
+                        let offset = e.utf8_error().valid_up_to();
+                        let msg = pretty_error("invalid UTF-8", line_num, e.as_bytes(), offset);
+                        Err(msg.into())
+                    }
+                    Err(_) => Err("stream did not contain valid UTF-8".into()),
+                }
+            });
+            let lines = keep_going(lines, config).filter_map(|result| match result {
+                Ok((_, line)) if line.chars().count() < config.min_length => {
+                    count_skipped(config);
+                    None
+                }
+                result => Some(result),
+            });
+            Box::new(lines.map(move |result| {
+                let (line_num, line) = result?;
+                let len = line.chars().count();
+                check_range(&selection, len, line_num, "character", config)?;
+                let selection = selection.at(len);
+                let cut = select_chars(&line, &selection, config.join.as_deref());
+                Ok(numbered(line_num, cut, config))
+            }))
+        }
+        Selector::Fields(positions) => field_lines(reader, positions, config),
+        Selector::Names(_) | Selector::NamesMatching(_) => {
+            match with_header_positions(reader, config) {
+                Ok((reader, positions)) => field_lines(reader, &positions, config),
+                Err(e) => Box::new(std::iter::once(Err(e))),
+            }
+        }
+    };
+    // Taken from the lines cut rather than those read, as NDJSON input may have lines to skip
+    lines.take(config.max_lines.unwrap_or(usize::MAX))
+}
+
+fn process_reader(reader: impl BufRead, config: &Config, out: &mut impl Write) -> CliResult<()> {
+    if config.count_fields {
+        return count_fields(reader, config, out);
+    }
+    match &config.selector {
+        Selector::Fields(positions) if config.color.is_some() => {
+            highlight_fields(reader, positions, config, out)
+        }
+        Selector::Fields(positions) => cut_fields(reader, positions, config, out),
+        Selector::Bytes(positions) => cut_bytes(reader, positions, config, out),
+        Selector::Names(_) | Selector::NamesMatching(_) => {
+            let (reader, positions) = with_header_positions(reader, config)?;
+            cut_fields(reader, &positions, config, out)
+        }
+        _ => {
+            for line in select_lines(reader, config) {
+                out.write_all(line?.as_bytes())?;
+                out.write_all(config.terminator.as_bytes())?;
+                if config.follow {
+                    out.flush()?;
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Starts and stops the colour of a field under --color, bold red like grep's matches.
+const HIGHLIGHT: &str = "\x1b[1;31m";
+const RESET: &str = "\x1b[0m";
+
+/// Writes out every line whole rather than cutting it, with the selected fields coloured in as
+/// long as colour is on. Lines are split up into the fields they'd be cut into, but what's
+/// written out is exactly what was read.
+fn highlight_fields(
+    reader: impl BufRead,
+    positions: &Positions,
+    config: &Config,
+    out: &mut impl Write,
+) -> CliResult<()> {
+    let selection = Selection::new(positions, false);
+    let delimiter = match &config.str_delim {
+        Some(delim) => delim.clone(),
+        None => char::from(config.delimiter).to_string(),
+    };
+    let colored = config.color == Some(true);
+    let lines = text_lines(reader, config.zero_terminated, false);
+    for line in lines.take(config.max_lines.unwrap_or(usize::MAX)) {
+        let line = line?;
+        let fields = fields_as_read(&line, config);
+        let selection = selection.at(fields.len());
+        for (idx, field) in fields.iter().enumerate() {
+            if idx > 0 {
+                out.write_all(delimiter.as_bytes())?;
+            }
+            match colored && selection.indices.contains(&idx) {
+                true => write!(out, "{}{}{}", HIGHLIGHT, field, RESET)?,
+                false => out.write_all(field.as_bytes())?,
+            }
+        }
+        out.write_all(config.terminator.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// The fields of `line` as they were read, quotes and all, split at the delimiters the fields would
+/// be cut at. As with the csv reader, a delimiter in quotes doesn't count, a quote only opens a
+/// field, and straight after the closing quote another is a quote in the field. A quoted field
+/// can't run on to the next line, though.
+fn fields_as_read<'l>(line: &'l str, config: &Config) -> Vec<&'l str> {
+    if let Some(delim) = &config.str_delim {
+        return line.split(delim.as_str()).collect();
+    }
+    let mut fields = Vec::new();
+    let (mut start, mut opening, mut quoted, mut closed) = (0, true, false, false);
+    for (idx, b) in line.bytes().enumerate() {
+        if b == config.delimiter && !quoted {
+            fields.push(&line[start..idx]);
+            start = idx + 1;
+            (opening, closed) = (true, false);
+            continue;
+        }
+        if b == b'"' && !config.no_quoting && (opening || quoted || closed) {
+            quoted = !quoted;
+            closed = !quoted;
+        } else {
+            closed = false;
+        }
+        opening = false;
+    }
+    fields.push(&line[start..]);
+    fields
+}
+
+/// Writes out the number of fields on each line instead of cutting any, to help track down lines
+/// which have more or fewer than the rest.
+fn count_fields(reader: impl BufRead, config: &Config, out: &mut impl Write) -> CliResult<()> {
+    for record in field_records(reader, config) {
+        write!(out, "{}", record?.len())?;
+        out.write_all(config.terminator.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// Reads the header line off `reader` to find the fields called `names`, handing back a reader
+/// which still starts with the header as that's cut along with the rest.
+fn with_header_positions(
+    mut reader: impl BufRead,
+    config: &Config,
+) -> CliResult<(impl BufRead, Positions)> {
+    let mut header = Vec::new();
+    let separator = if config.zero_terminated { b'\0' } else { b'\n' };
+    reader.read_until(separator, &mut header)?;
+    let positions = match &config.selector {
+        Selector::NamesMatching(regex) => header_matches(&header, regex, config)?,
+        Selector::Names(names) => header_positions(&header, names, config)?,
+        _ => unreachable!("only names are looked up in the header"),
+    };
+    Ok((io::Cursor::new(header).chain(reader), positions))
+}
+
+/// The fields of a header line.
+fn header_fields(header: &[u8], config: &Config) -> CliResult<StringRecord> {
+    let header = csv::ReaderBuilder::new()
+        .delimiter(config.delimiter)
+        .has_headers(false)
+        .quoting(!config.no_quoting)
+        .from_reader(header)
+        .into_records()
+        .next()
+        .transpose()?
+        .unwrap_or_default();
+    Ok(header)
+}
+
+/// The positions of the fields in a header line whose names match `regex`, of which there has to
+/// be at least one.
+fn header_matches(header: &[u8], regex: &Regex, config: &Config) -> CliResult<Positions> {
+    let positions = header_fields(header, config)?
+        .iter()
+        .enumerate()
+        .filter(|(_, field)| regex.is_match(field))
+        .map(|(idx, _)| idx..idx + 1)
+        .collect::<Positions>();
+    match positions.is_empty() {
+        true => Err(format!("kat: no field in the header matches '{}'", regex).into()),
+        false => Ok(merge_ranges(&positions)),
+    }
+}
+
+/// The positions of the fields called `names` in a header line, listed in the order they're
+/// named. Like any list of positions, they're cut in the order they come in the header all the
+/// same unless it's --keep-order. A name with a `*` in it is a glob standing for all the fields
+/// it matches, in header order. A field the header hasn't got is only found if it has a
+/// --default, at the end of the record where `with_defaults` puts it.
+fn header_positions(header: &[u8], names: &[String], config: &Config) -> CliResult<Positions> {
+    let header = header_fields(header, config)?;
+    let missing = missing_defaults(&header, config);
+    let mut positions = Positions::new();
+    for name in names {
+        if name.contains('*') {
+            let glob = glob_regex(name);
+            let matched = header
+                .iter()
+                .enumerate()
+                .filter(|(_, field)| glob.is_match(field))
+                .map(|(idx, _)| idx..idx + 1)
+                .collect::<Positions>();
+            if matched.is_empty() {
+                return Err(format!("kat: no field in the header matches '{}'", name).into());
+            }
+            positions.extend(matched);
+            continue;
+        }
+        let idx = match header.iter().position(|field| field == name) {
+            Some(idx) => idx,
+            None => match missing.iter().position(|(missing, _)| missing == name) {
+                Some(nth) => header.len() + nth,
+                None => return Err(format!("kat: no field named '{}' in the header", name).into()),
+            },
+        };
+        positions.push(idx..idx + 1);
+    }
+    Ok(positions)
+}
+
+/// The regex for a header name glob, in which `*` matches anything and the rest only itself.
+fn glob_regex(glob: &str) -> Regex {
+    let pattern = glob
+        .split('*')
+        .map(regex::escape)
+        .collect::<Vec<_>>()
+        .join(".*");
+    Regex::new(&format!("^{}$", pattern)).expect("an escaped glob is a valid regex")
+}
+
+/// The --defaults of the fields selected by name which `header` hasn't got, in the order they
+/// were given.
+fn missing_defaults<'c>(header: &StringRecord, config: &'c Config) -> Vec<&'c (String, String)> {
+    let names = match &config.selector {
+        Selector::Names(names) => names,
+        _ => return Vec::new(),
+    };
+    config
+        .defaults
+        .iter()
+        .filter(|(name, _)| names.contains(name) && !header.iter().any(|field| field == name))
+        .collect()
+}
+
+/// Under --default, puts the fields the header (the first of `records`) is missing on the end of
+/// every record, the header getting their names and every other record their values. A record
+/// shorter than the header is made up to its length with empty fields first, so that the defaults
+/// are where `header_positions` expects them.
+fn with_defaults<'a>(
+    records: Box<dyn Iterator<Item = CliResult<StringRecord>> + 'a>,
+    config: &'a Config,
+) -> Box<dyn Iterator<Item = CliResult<StringRecord>> + 'a> {
+    if config.defaults.is_empty() {
+        return records;
+    }
+    let mut header: Option<(usize, Vec<&(String, String)>)> = None;
+    Box::new(records.map(move |result| {
+        let record = result?;
+        let (header_len, missing, added): (_, _, Vec<&str>) = match &header {
+            None => {
+                let missing = missing_defaults(&record, config);
+                let names = missing.iter().map(|(name, _)| name.as_str()).collect();
+                (record.len(), missing, names)
+            }
+            Some((header_len, missing)) => {
+                let values = missing.iter().map(|(_, value)| value.as_str()).collect();
+                (*header_len, missing.clone(), values)
+            }
+        };
+        let mut filled = record
+            .iter()
+            .chain(std::iter::repeat(""))
+            .take(header_len)
+            .chain(added)
+            .chain(record.iter().skip(header_len))
+            .collect::<StringRecord>();
+        filled.set_position(record.position().cloned());
+        header.get_or_insert((header_len, missing));
+        Ok(filled)
+    }))
+}
+
+/// The positions to write out of each record, along with those of them to hash.
+fn field_selection(positions: &Positions, config: &Config) -> (Selection, Indices) {
+    let hash_pos = config
+        .hash_fields
+        .as_ref()
+        .map(Indices::new)
+        .unwrap_or_default();
+    let selection = Selection::new(positions, config.keep_order)
+        .reversed(config.reverse)
+        .repeated(config.repeat);
+    (selection, hash_pos)
+}
+
+/// Splits the input up into records, leaving out any which are filtered out, but doesn't
+/// select any fields.
+fn field_records<'a>(
+    reader: impl BufRead + 'a,
+    config: &'a Config,
+) -> Box<dyn Iterator<Item = CliResult<StringRecord>> + 'a> {
+    let source: Box<dyn Iterator<Item = CliResult<StringRecord>> + 'a> =
+        match (&config.regex_delim, &config.str_delim, config.head_fields) {
+            (Some(regex), _, _) => {
+                let lines = text_lines(reader, config.zero_terminated, false).enumerate();
+                Box::new(lines.map(move |(idx, line)| {
+                    let record = StringRecord::from(regex.split(&line?).collect::<Vec<_>>());
+                    Ok(at_line(record, idx))
+                }))
+            }
+            (None, Some(delim), _) => {
+                let lines = text_lines(reader, config.zero_terminated, false).enumerate();
+                Box::new(lines.map(move |(idx, line)| {
+                    let record =
+                        StringRecord::from(line?.split(delim.as_str()).collect::<Vec<_>>());
+                    Ok(at_line(record, idx))
+                }))
+            }
+            // Only split off the first few fields, leaving the rest of the line as the last
+            (None, None, Some(n)) => {
+                let delimiter = char::from(config.delimiter);
+                let lines = text_lines(reader, config.zero_terminated, false).enumerate();
+                Box::new(lines.map(move |(idx, line)| {
+                    let record =
+                        StringRecord::from(line?.splitn(n + 1, delimiter).collect::<Vec<_>>());
+                    Ok(at_line(record, idx))
+                }))
+            }
+            (None, None, None) => {
+                // A line of one field is written out just as it was read, which the record can't
+                // say if it was quoted, so anything needing that has to have the line itself
+                let recording = (!config.no_quoting
+                    && echoes_unsplit(config)
+                    && !config.describe
+                    && config.group_sum.is_none())
+                .then(Rc::<RefCell<Recording>>::default);
+                let reader: Box<dyn Read + 'a> = match &recording {
+                    Some(recording) => Box::new(Recorder {
+                        inner: reader,
+                        recording: Rc::clone(recording),
+                    }),
+                    None => Box::new(reader),
+                };
+                let csv_reader = csv::ReaderBuilder::new()
+                    .delimiter(config.delimiter)
+                    .has_headers(false)
+                    .quoting(!config.no_quoting)
+                    .terminator(match config.zero_terminated {
+                        true => csv::Terminator::Any(b'\0'),
+                        false => csv::Terminator::CRLF,
+                    })
+                    // Lines without a delimiter are let off being short, which the csv reader
+                    // can't do, so we check the number of fields ourselves
+                    .flexible(true)
+                    .from_reader(reader);
+                // Squeezing may leave any line short of a few fields. Without quoting, a field
+                // which would have been quoted splits into however many delimiters it has.
+                // And --only-delimited has never minded, while --count-fields is there to find them
+                // and --tail-anchored to cut them whatever their length
+                let strict = !(config.only_delimited
+                    || config.squeeze
+                    || config.no_quoting
+                    || config.count_fields
+                    || config.tail_anchored);
+                let records: Box<dyn Iterator<Item = CliResult<StringRecord>> + 'a> = if config
+                    .pretty_errors
+                {
+                    Box::new(pretty_records(
+                        csv_reader,
+                        config.delimiter,
+                        strict,
+                        config.skip_lines,
+                    ))
+                } else {
+                    let records: Box<dyn Iterator<Item = CliResult<StringRecord>>> = match config
+                        .line_numbers
+                    {
+                        true => Box::new(numbered_records(csv_reader)),
+                        false => Box::new(csv_reader.into_records().map(|r| r.map_err(Into::into))),
+                    };
+                    match strict {
+                        true => Box::new(equal_lengths(records)),
+                        false => records,
+                    }
+                };
+                match recording {
+                    Some(recording) => Box::new(records.map(move |result| {
+                        result.map(|record| as_read(record, &recording, config.zero_terminated))
+                    })),
+                    None => records,
+                }
+            }
+        };
+    let source = with_defaults(keep_going(source, config), config);
+    let (header, source) = take_header(source, config);
+    let source: Box<dyn Iterator<Item = CliResult<StringRecord>> + 'a> = match config.squeeze {
+        // A run of delimiters is read as a run of empty fields, so dropping them all
+        // leaves the fields as if the run had been a single delimiter
+        true => Box::new(source.map(|result| {
+            result.map(|record| {
+                let mut squeezed: StringRecord = record.iter().filter(|f| !f.is_empty()).collect();
+                squeezed.set_position(record.position().cloned());
+                squeezed
+            })
+        })),
+        false => source,
+    };
+    let source: Box<dyn Iterator<Item = CliResult<StringRecord>> + 'a> = match config.hash_sample {
+        Some((field, fraction)) => Box::new(source.filter(move |result| {
+            let kept = result.as_ref().map_or(true, |record| {
+                hash_fraction(record.get(field).unwrap_or("")) < fraction
+            });
+            if !kept {
+                count_skipped(config);
+            }
+            kept
+        })),
+        None => source,
+    };
+    let source: Box<dyn Iterator<Item = CliResult<StringRecord>> + 'a> = match config.only_delimited
+    {
+        // A line without a delimiter is read as a record of one field
+        true => Box::new(source.filter(|result| {
+            let kept = result.as_ref().map_or(true, |r| r.len() > 1);
+            if !kept {
+                count_skipped(config);
+            }
+            kept
+        })),
+        false => source,
+    };
+    let records = header.into_iter().chain(source);
+    Box::new(records.take(config.max_lines.unwrap_or(usize::MAX)))
+}
+
+/// Sorts the records by --sort-by if there are any keys to sort them by.
+fn sorted<'a>(
+    records: Box<dyn Iterator<Item = CliResult<StringRecord>> + 'a>,
+    config: &Config,
+) -> CliResult<Box<dyn Iterator<Item = CliResult<StringRecord>> + 'a>> {
+    let keys = match &config.sort_by {
+        Some(keys) => keys,
+        None => return Ok(records),
+    };
+    let (header, records) = take_header(records, config);
+    let mut rows = header.into_iter().collect::<CliResult<Vec<_>>>()?;
+    let sort_from = rows.len();
+    rows.extend(records.collect::<CliResult<Vec<_>>>()?);
+    // sort_by is stable so rows which tie on every key keep their input order
+    rows[sort_from..].sort_by(|a, b| {
+        keys.iter()
+            .map(|key| key.compare(a, b))
+            .find(|o| o.is_ne())
+            .unwrap_or(Ordering::Equal)
+    });
+    Ok(Box::new(rows.into_iter().map(Ok)))
+}
+
+/// Under --keep-going, skips over each line which can't be read or split up with a warning on
+/// stderr, rather than stopping at it. There's no getting past an io error though.
+fn keep_going<'a, T: 'a>(
+    lines: impl Iterator<Item = CliResult<T>> + 'a,
+    config: &'a Config,
+) -> Box<dyn Iterator<Item = CliResult<T>> + 'a> {
+    if !config.keep_going {
+        return Box::new(lines);
+    }
+    Box::new(
+        with_line_numbers(lines, config).filter_map(|(line_num, result)| match result {
+            Err(e) if io_error(&e).is_none() => {
+                eprintln!("kat: skipping line {}: {}", line_num, e);
+                count_skipped(config);
+                None
+            }
+            result => Some(result),
+        }),
+    )
+}
+
+/// Under --header, takes the first record off `records` so that it can be kept out of the way of
+/// anything which would drop it or move it from the top.
+fn take_header<'a>(
+    mut records: Box<dyn Iterator<Item = CliResult<StringRecord>> + 'a>,
+    config: &Config,
+) -> (
+    Option<CliResult<StringRecord>>,
+    Box<dyn Iterator<Item = CliResult<StringRecord>> + 'a>,
+) {
+    let header = if config.header { records.next() } else { None };
+    (header, records)
+}
+
+/// A csv writer which writes out records the way kat does.
+fn record_writer<W: Write>(out: W, config: &Config) -> csv::Writer<W> {
+    let quote_style = if config.quoting == Quoting::Always {
+        QuoteStyle::Always
+    } else if config.head_fields.is_some()
+        || config.regex_delim.is_some()
+        || config.str_delim.is_some()
+        || config.no_quoting
+    {
+        // Quotes weren't interpreted when splitting so it would be odd to add any back
+        QuoteStyle::Never
+    } else if config.joiner.is_some() || config.group_separator.is_some() {
+        // The fields were joined up by hand, into what the writer would take for a single field
+        QuoteStyle::Never
+    } else if config.output_format == OutputFormat::Json {
+        // Each object is written as a single field, which mustn't be quoted like CSV
+        QuoteStyle::Never
+    } else {
+        match config.quoting {
+            // Strictly quoted fields are quoted by `write_selected` before they get here
+            Quoting::Strict | Quoting::Never => QuoteStyle::Never,
+            _ => QuoteStyle::Necessary,
+        }
+    };
+    csv::WriterBuilder::new()
+        .delimiter(config.output_delimiter)
+        .terminator(config.terminator.for_csv())
+        .quote_style(quote_style)
+        // Lines cut whatever their length may come out with fewer fields than those before
+        .flexible(true)
+        .from_writer(out)
+}
+
+/// The number of the line `record` was read from, counting any --skip-lines skipped.
+fn line_number(record: &StringRecord, config: &Config) -> usize {
+    // The csv reader counts lines by their newlines whatever the terminator
+    let line = record
+        .position()
+        .map_or(0, |p| match config.zero_terminated {
+            true => p.record() as usize + 1,
+            false => p.line() as usize,
+        });
+    config.skip_lines + line
+}
+
+/// The line `record` was read from, numbered if need be, if it hadn't a delimiter in it, as like
+/// cut we write those out as they are rather than cutting them. That's the one field it's made of,
+/// which the csv reader leaves just as it was read (see `as_read`).
+fn unsplit(record: &StringRecord, config: &Config) -> Option<String> {
+    let as_is = record.len() == 1 && echoes_unsplit(config);
+    as_is.then(|| numbered(line_number(record, config), record[0].to_owned(), config))
+}
+
+/// Whether lines without a delimiter are written out as they are. A line squeezed down to a
+/// single field might have had delimiters, so with --squeeze every line is cut, as is every line
+/// when the first field is selected to be hashed.
+fn echoes_unsplit(config: &Config) -> bool {
+    let selected = |idx| match &config.selector {
+        // Whatever counts back from the end might be the first field of a line with only one
+        Selector::Fields(positions) => {
+            Selection::new(positions, false).anchored || Indices::new(positions).contains(&idx)
+        }
+        _ => true,
+    };
+    let hashes_first = config
+        .hash_fields
+        .as_ref()
+        .is_some_and(|positions| Indices::new(positions).contains(&0) && selected(0));
+    !config.squeeze && !hashes_first && config.output_format == OutputFormat::Delimited
+}
+
+/// What's been read through a `Recorder` since the record it's up to, which starts `start` bytes
+/// into the input.
+#[derive(Default)]
+struct Recording {
+    start: u64,
+    bytes: Vec<u8>,
+}
+
+/// A reader which keeps what the csv reader reads through it in `recording`, so that a record
+/// can be had back as it was read.
+struct Recorder<R> {
+    inner: R,
+    recording: Rc<RefCell<Recording>>,
+}
+
+impl<R: Read> Read for Recorder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.recording.borrow_mut().bytes.extend(&buf[..len]);
+        Ok(len)
+    }
+}
+
+/// `record` as it was read if it's a single field, quotes and all, rather than as the csv reader
+/// unquoted it. Anything read before it is let go of, as it won't be needed again.
+fn as_read(
+    record: StringRecord,
+    recording: &RefCell<Recording>,
+    zero_terminated: bool,
+) -> StringRecord {
+    let mut recording = recording.borrow_mut();
+    let start = record.position().map_or(recording.start, |p| p.byte());
+    let seen = (start.saturating_sub(recording.start) as usize).min(recording.bytes.len());
+    recording.bytes.drain(..seen);
+    recording.start = start;
+    if record.len() != 1 {
+        return record;
+    }
+    let ends_line = |b: u8| match zero_terminated {
+        true => b == b'\0',
+        false => matches!(b, b'\r' | b'\n'),
+    };
+    // The record starts wherever the last one ended, before any blank lines the reader skipped.
+    // It ends at the first terminator which isn't quoted, as the csv reader would have it: a quote
+    // only opens a field, and straight after the closing quote another is a quote in the field
+    let (mut opening, mut quoted, mut closed) = (true, false, false);
+    let line = recording
+        .bytes
+        .iter()
+        .copied()
+        .skip_while(|&b| ends_line(b))
+        .take_while(|&b| {
+            if b == b'"' && (opening || quoted || closed) {
+                quoted = !quoted;
+                closed = !quoted;
+            } else {
+                closed = false;
+            }
+            opening = false;
+            quoted || !ends_line(b)
+        })
+        .collect::<Vec<_>>();
+    let mut as_read = StringRecord::from(vec![String::from_utf8_lossy(&line)]);
+    as_read.set_position(record.position().cloned());
+    as_read
+}
+
+/// Lets the csv writer share `out` with the lines we write out as they are, as long as it's
+/// flushed before any of them.
+struct Shared<'a, W>(&'a RefCell<W>);
+
+impl<W: Write> Write for Shared<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.borrow_mut().flush()
+    }
+}
+
+/// Writes out the selected fields of `record`, hashing any in `hash_pos`.
+fn write_selected<W: Write>(
+    csv_writer: &mut csv::Writer<W>,
+    record: &StringRecord,
+    selection: &Selection,
+    hash_pos: &Indices,
+    config: &Config,
+) -> CliResult<()> {
+    let line_num = line_number(record, config);
+    check_range(selection, record.len(), line_num, "field", config)?;
+    let selection = selection.at(record.len());
+    if config.line_numbers && config.joiner.is_none() {
+        csv_writer.write_field(line_num.to_string())?;
+    }
+    if config.output_format == OutputFormat::Json {
+        let fields = selection.of(record.len()).map(|idx| {
+            (
+                idx,
+                field_at(record, idx, hash_pos, config.trim, config.na.as_deref()).into_owned(),
+            )
+        });
+        csv_writer.write_field(json_object(fields))?;
+    } else if let Some(separator) = &config.group_separator {
+        // Each run of consecutive fields is joined up as it would be anyway, then the runs with
+        // the separator, and the lot written as one field
+        let mut groups: Vec<Vec<Cow<str>>> = Vec::new();
+        let mut prev = None;
+        for idx in selection.of(record.len()) {
+            // A field picked out over and over again is in a run with itself
+            if prev.is_none_or(|prev| idx != prev && idx != prev + 1) {
+                groups.push(Vec::new());
+            }
+            let field = field_at(record, idx, hash_pos, config.trim, config.na.as_deref());
+            groups.last_mut().unwrap().push(field);
+            prev = Some(idx);
+        }
+        let line = groups
+            .iter()
+            .map(|group| match &config.joiner {
+                Some(joiner) => group.join(joiner),
+                None => join_fields(group.iter().map(|f| f.as_bytes()), config.output_delimiter),
+            })
+            .collect::<Vec<_>>()
+            .join(separator);
+        // Without a joiner the line number was written out as a field of its own
+        let line = match config.joiner {
+            Some(_) => numbered(line_num, line, config),
+            None => line,
+        };
+        csv_writer.write_field(line)?;
+    } else if let Some(joiner) = &config.joiner {
+        // So we join the fields up ourselves (quoting them first, if need be, as the writer won't)
+        // and have the writer write them as one
+        let mut fields = Vec::new();
+        if config.line_numbers {
+            fields.push(Cow::Owned(line_num.to_string()));
+        }
+        let na = config.na.as_deref();
+        let selected = selected_fields(record, &selection, hash_pos, config.trim, na);
+        let lone = selected.len() == 1 && !config.line_numbers;
+        fields.extend(selected.into_iter().map(|field| match config.quoting {
+            Quoting::Strict if lone && field.is_empty() => Cow::Borrowed("\"\""),
+            Quoting::Strict => Cow::Owned(strictly_quoted(&field, joiner.as_bytes()).into_owned()),
+            _ => field,
+        }));
+        csv_writer.write_field(fields.join(joiner))?;
+    } else {
+        let na = config.na.as_deref();
+        let fields = selected_fields(record, &selection, hash_pos, config.trim, na);
+        // A lone empty field has to be quoted too, or it would read back as a blank line
+        let lone = fields.len() == 1 && !config.line_numbers;
+        for field in &fields {
+            match config.quoting {
+                Quoting::Strict if lone && field.is_empty() => csv_writer.write_field("\"\"")?,
+                Quoting::Strict => csv_writer.write_field(
+                    strictly_quoted(field, std::slice::from_ref(&config.output_delimiter))
+                        .as_bytes(),
+                )?,
+                _ => csv_writer.write_field(field.as_bytes())?,
+            }
+        }
+    }
+    csv_writer.write_record(None::<&[u8]>)?;
+    Ok(())
+}
+
+/// Quotes `field` if it has `delimiter`, a quote, CR or LF in it, doubling any quotes inside it.
+fn strictly_quoted<'f>(field: &'f str, delimiter: &[u8]) -> Cow<'f, str> {
+    let needs_quotes = field.bytes().any(|b| matches!(b, b'"' | b'\r' | b'\n'))
+        || field
+            .as_bytes()
+            .windows(delimiter.len())
+            .any(|window| window == delimiter);
+    match needs_quotes {
+        true => Cow::Owned(format!("\"{}\"", field.replace('"', "\"\""))),
+        false => Cow::Borrowed(field),
+    }
+}
+
+/// The lines `select_lines` hands back when cutting fields.
+fn field_lines<'a>(
+    reader: impl BufRead + 'a,
+    positions: &Positions,
+    config: &'a Config,
+) -> Box<dyn Iterator<Item = CliResult<String>> + 'a> {
+    let records = match sorted(field_records(reader, config), config) {
+        Ok(records) => records,
+        Err(e) => return Box::new(std::iter::once(Err(e))),
+    };
+    let (selection, hash_pos) = field_selection(positions, config);
+    let lines = records.map(move |result| {
+        let record = result?;
+        if let Some(line) = unsplit(&record, config) {
+            return Ok(line);
+        }
+        let mut csv_writer = record_writer(vec![], config);
+        write_selected(&mut csv_writer, &record, &selection, &hash_pos, config)?;
+        // Writing to a Vec can't fail
+        let mut line = csv_writer.into_inner().unwrap();
+        line.truncate(line.len() - config.terminator.as_bytes().len());
+        Ok(String::from_utf8(line)?)
+    });
+    match config.headers_only {
+        true => Box::new(lines.take(1)),
+        false => Box::new(lines),
+    }
+}
+
+fn cut_fields(
+    reader: impl BufRead,
+    positions: &Positions,
+    config: &Config,
+    out: &mut impl Write,
+) -> CliResult<()> {
+    let all_pos = Indices::new(positions);
+    if config.describe {
+        return describe(reader, &all_pos, config, out);
+    }
+    let source = field_records(reader, config);
+    if let Some((key, value)) = config.group_sum {
+        return group_sum(source, key, value, config, out);
+    }
+    let (selection, hash_pos) = field_selection(positions, config);
+    let out = RefCell::new(out);
+    let mut csv_writer = record_writer(Shared(&out), config);
+
+    let count_empties = config.empty_report || config.null_ratio;
+    // Every column selected (bar those open-ended) is counted, so only list them if counting
+    let mut columns = match count_empties {
+        true => all_pos.up_to(0),
+        false => Vec::new(),
+    };
+    let mut empty_counts = vec![0; columns.len()];
+    let mut total_rows = 0;
+
+    for result in sorted(source, config)? {
+        let record = result?;
+        match unsplit(&record, config) {
+            Some(line) => {
+                csv_writer.flush()?;
+                let mut out = out.borrow_mut();
+                out.write_all(line.as_bytes())?;
+                out.write_all(config.terminator.as_bytes())?;
+            }
+            None => write_selected(&mut csv_writer, &record, &selection, &hash_pos, config)?,
+        }
+        if config.follow {
+            // Otherwise the records would sit in the writer's buffer for who knows how long
+            csv_writer.flush()?;
+        }
+        if config.headers_only {
+            break;
+        }
+        if count_empties {
+            if total_rows == 0 {
+                // Open-ended ranges only know where they stop once we've seen a record
+                columns = all_pos.up_to(record.len());
+                empty_counts = vec![0; columns.len()];
+            }
+            total_rows += 1;
+            for (count, &idx) in empty_counts.iter_mut().zip(&columns) {
+                if record.get(idx).is_none_or(str::is_empty) {
+                    *count += 1;
+                }
+            }
+        }
+    }
+    // Flush explicitly as any error flushing on drop would go unnoticed
+    csv_writer.flush()?;
+
+    if config.empty_report {
+        eprintln!("field\tempty");
+        for (count, idx) in empty_counts.iter().zip(&columns) {
+            eprintln!("{}\t{}", idx + 1, count);
+        }
+    }
+    if config.null_ratio {
+        eprintln!("field\tnull_ratio");
+        for (count, idx) in empty_counts.iter().zip(&columns) {
+            let ratio = match total_rows {
+                0 => 0.0,
+                _ => 100.0 * *count as f64 / total_rows as f64,
+            };
+            eprintln!("{}\t{:.2}%", idx + 1, ratio);
+        }
+    }
+    Ok(())
+}
+
+/// The values of `keys` in each line of NDJSON as a delimited record. Keys which are missing (or
+/// null) are given as `config.missing_as` while nested values are kept as JSON.
+fn key_lines<'a>(
+    reader: impl BufRead + 'a,
+    keys: &'a [String],
+    config: &'a Config,
+) -> impl Iterator<Item = CliResult<String>> + 'a {
+    with_line_numbers(text_lines(reader, false, false), config).filter_map(
+        move |(line_num, line)| {
+            let line = match line {
+                Ok(line) if line.trim().is_empty() => {
+                    count_skipped(config);
+                    return None;
+                }
+                Ok(line) => line,
+                Err(e) => return Some(Err(e)),
+            };
+            Some(match key_values(&line, keys, &config.missing_as) {
+                Some(values) => Ok(join_fields(values, config.delimiter)),
+                None => Err(format!("kat: line {} is not a JSON object", line_num).into()),
+            })
+        },
+    )
+}
+
+/// The values of `keys` in a line of NDJSON, or None if the line isn't a JSON object.
+#[cfg(feature = "ndjson")]
+fn key_values(line: &str, keys: &[String], missing_as: &str) -> Option<Vec<String>> {
+    use serde_json::Value;
+
+    let object = match serde_json::from_str(line) {
+        Ok(Value::Object(object)) => object,
+        _ => return None,
+    };
+    let values = keys
+        .iter()
+        .map(|key| match object.get(key) {
+            None | Some(Value::Null) => missing_as.to_owned(),
+            Some(Value::String(s)) => s.clone(),
+            Some(value) => value.to_string(),
+        })
+        .collect();
+    Some(values)
+}
+
+#[cfg(not(feature = "ndjson"))]
+fn key_values(_line: &str, _keys: &[String], _missing_as: &str) -> Option<Vec<String>> {
+    None
+}
+
+/// Formats (0-based) fields as a JSON object keyed by their 1-based field numbers, in the order
+/// given. We don't go through a serde_json Map as that would sort "10" ahead of "2".
+#[cfg(feature = "ndjson")]
+fn json_object(fields: impl Iterator<Item = (usize, String)>) -> String {
+    use serde_json::Value;
+
+    let members = fields
+        .map(|(idx, val)| {
+            format!(
+                "{}:{}",
+                Value::from((idx + 1).to_string()),
+                Value::from(val)
+            )
+        })
+        .collect::<Vec<_>>();
+    format!("{{{}}}", members.join(","))
+}
+
+// parse_config refuses --output-format json when the feature is off
+#[cfg(not(feature = "ndjson"))]
+fn json_object(_fields: impl Iterator<Item = (usize, String)>) -> String {
+    unreachable!("kat was built without the 'ndjson' feature")
+}
+
+/// A minimal group-by: sums field `value` for each distinct value of field `key`, writing out the
+/// groups in the order they were first seen. Records whose value isn't a number (a header line
+/// most likely) don't count towards any group.
+fn group_sum(
+    records: impl Iterator<Item = CliResult<StringRecord>>,
+    key: usize,
+    value: usize,
+    config: &Config,
+    out: &mut impl Write,
+) -> CliResult<()> {
+    let mut groups: Vec<(String, f64)> = Vec::new();
+    let mut group_idx = HashMap::new();
+    for result in records {
+        let record = result?;
+        let amount = match record.get(value).and_then(|v| v.trim().parse::<f64>().ok()) {
+            Some(amount) => amount,
+            None => continue,
+        };
+        let name = record.get(key).unwrap_or("");
+        let idx = *group_idx.entry(name.to_owned()).or_insert_with(|| {
+            groups.push((name.to_owned(), 0.0));
+            groups.len() - 1
+        });
+        groups[idx].1 += amount;
+    }
+
+    let mut csv_writer = csv::WriterBuilder::new()
+        .delimiter(config.output_delimiter)
+        .terminator(config.terminator.for_csv())
+        .from_writer(&mut *out);
+    for (name, sum) in groups {
+        csv_writer.write_record([name, sum.to_string()])?;
+    }
+    csv_writer.flush()?;
+    Ok(())
+}
+
+/// Checks that a record of `len` fields has as many as the first record which split into more
+/// than one, handing back the number it should have had if not. A line without a delimiter is
+/// written out as it is, so it gets away with being short.
+fn unequal_length(expected: &mut Option<usize>, len: usize) -> Option<usize> {
+    match *expected {
+        _ if len == 1 => None,
+        None => {
+            *expected = Some(len);
+            None
+        }
+        Some(expected) => (expected != len).then_some(expected),
+    }
+}
+
+/// Fails on the first record with the wrong number of fields, as a csv reader which isn't
+/// flexible would.
+fn equal_lengths(
+    records: impl Iterator<Item = CliResult<StringRecord>>,
+) -> impl Iterator<Item = CliResult<StringRecord>> {
+    let mut expected = None;
+    records.map(move |result| {
+        let record = result?;
+        match unequal_length(&mut expected, record.len()) {
+            None => Ok(record),
+            Some(expected_len) => {
+                let p = record
+                    .position()
+                    .cloned()
+                    .unwrap_or_else(csv::Position::new);
+                let msg = format!(
+                    "CSV error: record {} (line: {}, byte: {}): found record with {} fields, but \
+                     the previous record has {} fields",
+                    p.record(),
+                    p.line(),
+                    p.byte(),
+                    record.len(),
+                    expected_len
+                );
+                Err(msg.into())
+            }
+        }
+    })
+}
+
+/// Reads records the way `csv::Reader::records` does, except that each one's position has the
+/// line it really starts on.
+fn numbered_records<R: Read>(
+    mut csv_reader: csv::Reader<R>,
+) -> impl Iterator<Item = CliResult<StringRecord>> {
+    let mut raw = ByteRecord::new();
+    std::iter::from_fn(move || match csv_reader.read_byte_record(&mut raw) {
+        Ok(false) => None,
+        Ok(true) => {
+            set_start_line(&mut raw, csv_reader.position().line());
+            Some(StringRecord::from_byte_record(raw.clone()).map_err(|e| e.to_string().into()))
+        }
+        Err(e) => Some(Err(e.into())),
+    })
+}
+
+/// The csv reader gives a record the position it starts looking for it from, which is short of the
+/// line it's actually on by however many blank lines it skips on the way. So we work back from
+/// `next_line`, the line the reader moved on to having read the record. The one case this can't
+/// get right is a last line missing its newline straight after a blank line.
+fn set_start_line(raw: &mut ByteRecord, next_line: u64) {
+    let newlines = raw.as_slice().iter().filter(|&&b| b == b'\n').count() as u64;
+    if let Some(mut position) = raw.position().cloned() {
+        let line = position.line().max(next_line.saturating_sub(newlines + 1));
+        position.set_line(line);
+        raw.set_position(Some(position));
+    }
+}
+
+/// Reads records the way `csv::Reader::records` does, except that UTF-8 and ragged record errors
+/// are reported with `pretty_error`. The csv reader doesn't hand us the raw line, so the line we
+/// quote is an approximation made by joining the record's fields back up with the delimiter. Line
+/// numbers count the `skipped` lines before the reader's first too.
+fn pretty_records<R: Read>(
+    mut csv_reader: csv::Reader<R>,
+    delimiter: u8,
+    strict: bool,
+    skipped: usize,
+) -> impl Iterator<Item = CliResult<StringRecord>> {
+    let mut raw = ByteRecord::new();
+    let mut expected = None;
+    std::iter::from_fn(move || {
+        let line_of = |raw: &ByteRecord| skipped + raw.position().map_or(0, |p| p.line() as usize);
+        let joined = |raw: &ByteRecord| raw.iter().collect::<Vec<_>>().join(&delimiter);
+        // The byte offset of the start of field `idx` in the joined up line
+        let field_offset = |raw: &ByteRecord, idx: usize| -> usize {
+            raw.iter().take(idx).map(|field| field.len() + 1).sum()
+        };
+        match csv_reader.read_byte_record(&mut raw) {
+            Ok(false) => None,
+            Ok(true) => Some({
+                set_start_line(&mut raw, csv_reader.position().line());
+                if let Some(expected_len) =
+                    unequal_length(&mut expected, raw.len()).filter(|_| strict)
+                {
+                    let msg = format!(
+                        "found {} fields where {} were expected",
+                        raw.len(),
+                        expected_len
+                    );
+                    let offset = field_offset(&raw, expected_len);
+                    return Some(Err(pretty_error(
+                        &msg,
+                        line_of(&raw),
+                        &joined(&raw),
+                        offset,
+                    )
+                    .into()));
+                }
+                StringRecord::from_byte_record(raw.clone()).map_err(|e| {
+                    let utf8_error = e.utf8_error();
+                    let offset = field_offset(&raw, utf8_error.field()) + utf8_error.valid_up_to();
+                    pretty_error("invalid UTF-8", line_of(&raw), &joined(&raw), offset).into()
+                })
+            }),
+            Err(e) => Some(Err(e.into())),
+        }
+    })
+}
+
+/// Formats a rustc style diagnostic which quotes `line` with a caret under byte `offset`.
+fn pretty_error(message: &str, line_num: usize, line: &[u8], offset: usize) -> String {
+    let text = String::from_utf8_lossy(line);
+    let column = String::from_utf8_lossy(&line[..offset.min(line.len())])
+        .chars()
+        .count();
+    let width = line_num.to_string().len();
+    format!(
+        "kat: {} on line {}, column {}\n{:w$} |\n{} | {}\n{:w$} | {:c$}^",
+        message,
+        line_num,
+        column + 1,
+        "",
+        line_num,
+        text,
+        "",
+        "",
+        w = width,
+        c = column
+    )
+}
+
+/// Anonymizes a value as the first 8 hex digits of its SHA-256 digest.
+#[cfg(feature = "hash")]
+fn short_hash(val: &str) -> String {
+    use sha2::{Digest, Sha256};
+    Sha256::digest(val.as_bytes())
+        .iter()
+        .take(4)
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Maps a value to a fraction in [0, 1] using its SHA-256 digest, so the same value always lands
+/// in the same spot whatever file or run it turns up in.
+#[cfg(feature = "hash")]
+fn hash_fraction(val: &str) -> f64 {
+    use sha2::{Digest, Sha256};
+    let digest = Sha256::digest(val.as_bytes());
+    let prefix = u64::from_be_bytes(digest[..8].try_into().unwrap());
+    prefix as f64 / u64::MAX as f64
+}
+
+// parse_config refuses --hash-fields and --hash-sample when the feature is off, so there is never
+// anything to hash
+#[cfg(not(feature = "hash"))]
+fn short_hash(_val: &str) -> String {
+    unreachable!("kat was built without the 'hash' feature")
+}
+
+#[cfg(not(feature = "hash"))]
+fn hash_fraction(_val: &str) -> f64 {
+    unreachable!("kat was built without the 'hash' feature")
+}
+
+/// Prints the inferred type, null count and number of distinct values of each selected column,
+/// looking at no more than `config.sample` records. The first record is treated as a header if
+/// it's made up of non-empty strings sitting on top of at least one column which isn't a string.
+/// The records are split up just as they would be to cut them.
+fn describe(
+    reader: impl BufRead,
+    all_pos: &Indices,
+    config: &Config,
+    out: &mut impl Write,
+) -> CliResult<()> {
+    let records = field_records(reader, config)
+        .take(config.sample)
+        .collect::<CliResult<Vec<_>>>()?;
+    let columns = all_pos.up_to(records.iter().map(StringRecord::len).max().unwrap_or(0));
+
+    let infer = |rows: &[StringRecord], idx: usize| {
+        let mut column_type = None;
+        let mut nulls = 0;
+        let mut distinct = HashSet::new();
+        for row in rows {
+            match row.get(idx).unwrap_or("") {
+                "" => nulls += 1,
+                val => {
+                    let val_type = ColumnType::of(val);
+                    column_type =
+                        Some(column_type.map_or(val_type, |t: ColumnType| t.widen(val_type)));
+                    distinct.insert(val.to_owned());
+                }
+            }
+        }
+        (
+            column_type.unwrap_or(ColumnType::Str),
+            nulls,
+            distinct.len(),
+        )
+    };
+
+    let has_header = records.len() > 1
+        && columns.iter().all(|&idx| {
+            let val = records[0].get(idx).unwrap_or("");
+            !val.is_empty() && ColumnType::of(val) == ColumnType::Str
+        })
+        && columns
+            .iter()
+            .any(|&idx| infer(&records[1..], idx).0 != ColumnType::Str);
+    let rows = if has_header {
+        &records[1..]
+    } else {
+        &records[..]
+    };
+
+    writeln!(out, "column\ttype\tnulls\tdistinct")?;
+    for idx in columns {
+        let name = if has_header {
+            records[0].get(idx).unwrap_or("").to_owned()
+        } else {
+            (idx + 1).to_string()
+        };
+        let (column_type, nulls, distinct) = infer(rows, idx);
+        writeln!(out, "{}\t{}\t{}\t{}", name, column_type, nulls, distinct)?;
+    }
+    Ok(())
+}
+
+fn open(file: &str, config: &Config) -> CliResult<Box<dyn BufRead>> {
+    let reader = open_raw(file, config)?;
+    let reader: Box<dyn BufRead> = match &config.encoding {
+        Some(label) => Box::new(BufReader::new(decode(reader, label))),
+        None => reader,
+    };
+    let terminator = if config.zero_terminated { b'\0' } else { b'\n' };
+    let tallies = [
+        config
+            .stats
+            .clone()
+            .map(|stats| stats as Arc<dyn ReadTally>),
+        config
+            .progress
+            .clone()
+            .map(|progress| progress as Arc<dyn ReadTally>),
+    ];
+    let mut reader = reader;
+    for tally in tallies.into_iter().flatten() {
+        reader = Box::new(CountingReader {
+            inner: reader,
+            lines: LineCounter::new(terminator),
+            tally,
+        });
+    }
+    // Skipped as soon as the file's open, so that nothing else (a header included) sees them,
+    // though they count as read for --stats and towards the line numbers of those after them
+    for _ in 0..config.skip_lines {
+        if reader.skip_until(terminator)? == 0 {
+            break;
+        }
+        count_skipped(config);
+    }
+    Ok(reader)
+}
+
+fn open_raw(file: &str, config: &Config) -> CliResult<Box<dyn BufRead>> {
+    match file {
+        "-" => Ok(decompressed(stdin(), config.decompress)?),
+        // Opening a directory works well enough, it's only reading it which fails, and then with
+        // an error which doesn't say much
+        _ if Path::new(file).is_dir() => {
+            Err("Is a directory (use -r to cut the files in it)".into())
+        }
+        _ => {
+            let handle = open_retrying(config.retry_on_lock, || File::open(file))?;
+            let compression = config.decompress.or_else(|| Compression::of_file(file));
+            Ok(decompressed(handle, compression)?)
+        }
+    }
+}
+
+/// The size of all of `files` put together, as long as they're all regular files (and none of them
+/// is compressed).
+fn total_size(files: &[String]) -> Option<u64> {
+    files
+        .iter()
+        .map(
+            |file| match file == "-" || Compression::of_file(file).is_some() {
+                true => None,
+                false => fs::metadata(file)
+                    .ok()
+                    .filter(|m| m.is_file())
+                    .map(|m| m.len()),
+            },
+        )
+        .sum()
+}
+
+/// Reads the newline-separated names of the files to cut out of `list`, or stdin if it's `-`.
+fn read_file_list(list: &str) -> CliResult<Vec<String>> {
+    let list_err = |e: io::Error| format!("kat: {}: {}", list, e);
+    let reader: Box<dyn BufRead> = match list {
+        "-" => Box::new(BufReader::new(stdin())),
+        _ => Box::new(BufReader::new(File::open(list).map_err(list_err)?)),
+    };
+    let mut files = Vec::new();
+    for line in reader.lines() {
+        let line = line.map_err(list_err)?;
+        let file = line.trim_end_matches('\r');
+        if !file.is_empty() {
+            files.push(file.to_owned());
+        }
+    }
+    Ok(files)
+}
+
+/// Replaces each directory in `files` with the regular files under it, in name order. Symlinks
+/// are followed but no directory is walked twice, so a link back up the tree can't loop forever.
+fn expand_dirs(files: Vec<String>) -> CliResult<Vec<String>> {
+    let mut expanded = Vec::new();
+    let mut walked = HashSet::new();
+    for file in files {
+        match file != "-" && Path::new(&file).is_dir() {
+            true => walk_dir(Path::new(&file), &mut walked, &mut expanded)?,
+            false => expanded.push(file),
+        }
+    }
+    Ok(expanded)
+}
+
+fn walk_dir(dir: &Path, walked: &mut HashSet<PathBuf>, files: &mut Vec<String>) -> CliResult<()> {
+    let walk_err = |e: io::Error| format!("kat: {}: {}", dir.display(), e);
+    if !walked.insert(dir.canonicalize().map_err(walk_err)?) {
+        return Ok(());
+    }
+    let mut entries = fs::read_dir(dir)
+        .and_then(|entries| {
+            entries
+                .map(|entry| entry.map(|e| e.path()))
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .map_err(walk_err)?;
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            walk_dir(&path, walked, files)?;
+        } else if path.is_file() {
+            files.push(path.to_string_lossy().into_owned());
+        }
+    }
+    Ok(())
+}
+
+/// Reads through `reader`, decompressing what it reads if it's `compression`ed. A format kat's
+/// been built without is read as it is.
+fn decompressed(
+    reader: impl Read + 'static,
+    compression: Option<Compression>,
+) -> io::Result<Box<dyn BufRead>> {
+    Ok(match compression.filter(Compression::built_in) {
+        Some(Compression::Gzip) => Box::new(BufReader::new(gunzip(reader))),
+        Some(Compression::Zstd) => Box::new(BufReader::new(unzstd(reader)?)),
+        Some(Compression::Bzip2) => Box::new(BufReader::new(bunzip2(reader))),
+        None => Box::new(BufReader::new(reader)),
+    })
+}
+
+#[cfg(feature = "gzip")]
+fn gunzip(reader: impl Read) -> impl Read {
+    flate2::read::MultiGzDecoder::new(reader)
+}
+
+// Nothing is decompressed unless the feature is on
+#[cfg(not(feature = "gzip"))]
+fn gunzip(reader: impl Read) -> impl Read {
+    reader
+}
+
+#[cfg(feature = "zstd")]
+fn unzstd(reader: impl Read) -> io::Result<impl Read> {
+    zstd::stream::read::Decoder::new(reader)
+}
+
+#[cfg(not(feature = "zstd"))]
+fn unzstd(reader: impl Read) -> io::Result<impl Read> {
+    Ok(reader)
+}
+
+#[cfg(feature = "bzip2")]
+fn bunzip2(reader: impl Read) -> impl Read {
+    bzip2::read::MultiBzDecoder::new(reader)
+}
+
+#[cfg(not(feature = "bzip2"))]
+fn bunzip2(reader: impl Read) -> impl Read {
+    reader
+}
+
+/// Decodes what `reader` reads from the encoding called `label` into UTF-8, or from whatever
+/// encoding its BOM says it's in if it starts with one.
+#[cfg(feature = "encoding")]
+fn decode(reader: impl Read, label: &str) -> impl Read {
+    // The label is one of the few --encoding allows, all of which encoding_rs knows
+    let encoding = encoding_rs::Encoding::for_label(label.as_bytes()).unwrap();
+    Decoded {
+        inner: reader,
+        decoder: encoding.new_decoder(),
+        decoded: Vec::new(),
+        pos: 0,
+        done: false,
+    }
+}
+
+// open only asks for the input to be decoded when the feature is on
+#[cfg(not(feature = "encoding"))]
+fn decode(reader: impl Read, _label: &str) -> impl Read {
+    reader
+}
+
+/// A reader which hands out what its inner reader reads once it's been decoded into UTF-8.
+#[cfg(feature = "encoding")]
+struct Decoded<R> {
+    inner: R,
+    decoder: encoding_rs::Decoder,
+    /// What's been decoded but not yet read, from `pos` on
+    decoded: Vec<u8>,
+    pos: usize,
+    /// Whether the inner reader has run dry
+    done: bool,
+}
+
+#[cfg(feature = "encoding")]
+impl<R: Read> Read for Decoded<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.decoded.len() {
+            if self.done {
+                return Ok(0);
+            }
+            let mut raw = [0; 8 * 1024];
+            let len = self.inner.read(&mut raw)?;
+            self.done = len == 0;
+            // Made big enough for the decoder to get through all it's given in one go
+            let max_len = self
+                .decoder
+                .max_utf8_buffer_length(len)
+                .unwrap_or(usize::MAX);
+            self.decoded.resize(max_len, 0);
+            let (_, _, written, _) =
+                self.decoder
+                    .decode_to_utf8(&raw[..len], &mut self.decoded, self.done);
+            self.decoded.truncate(written);
+            self.pos = 0;
+        }
+        let len = buf.len().min(self.decoded.len() - self.pos);
+        buf[..len].copy_from_slice(&self.decoded[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+/// Calls `open` up to `retries` more times, backing off a little longer each time, for as long as
+/// it fails because some other process has the file locked.
+fn open_retrying<T>(retries: u32, mut open: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+    let mut attempt = 0;
+    loop {
+        match open() {
+            Err(e) if attempt < retries && is_sharing_violation(&e) => {
+                attempt += 1;
+                thread::sleep(Duration::from_millis(50 * u64::from(attempt)));
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Only Windows refuses to open files which are in use, with ERROR_SHARING_VIOLATION or
+/// ERROR_LOCK_VIOLATION. Elsewhere these codes mean something else entirely (e.g. EPIPE).
+fn is_sharing_violation(e: &io::Error) -> bool {
+    cfg!(windows) && matches!(e.raw_os_error(), Some(32) | Some(33))
+}
+
+pub fn parse_config(cmd_args: Vec<String>) -> CliResult<Config> {
+    let app = App::new("kat")
+        .version("0.1.0")
+        .author("sanjayts")
+        .arg(
+            Arg::new("delimiter")
+                .value_name("DELIM")
+                .short('d')
+                .long("delimiter")
+                .help("Use DELIM instead of TAB for delimiter")
+                .default_value("\t")
+                .hide_default_value(true)
+                .takes_value(true)
+                .multiple_values(false),
+        )
+        .arg(
+            Arg::new("tab")
+                .long("tab")
+                .help("use TAB for delimiter, as it is by default")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("delimiter"),
+        )
+        .arg(
+            Arg::new("regex_delim")
+                .value_name("PATTERN")
+                .long("regex-delim")
+                .help("split fields on matches of the regular expression PATTERN")
+                .takes_value(true)
+                .conflicts_with_all(&["delimiter", "byte_list", "char_list", "head_fields"]),
+        )
+        .arg(
+            Arg::new("str_delim")
+                .value_name("STR")
+                .long("str-delim")
+                .help("split fields on the string STR, which can be more than one character")
+                .takes_value(true)
+                .conflicts_with_all(&[
+                    "delimiter",
+                    "regex_delim",
+                    "byte_list",
+                    "char_list",
+                    "head_fields",
+                ]),
+        )
+        .arg(
+            Arg::new("delim_char")
+                .value_name("CHAR")
+                .long("delim-char")
+                .help("split fields on CHAR, which can be any character rather than just a byte")
+                .takes_value(true)
+                .conflicts_with_all(&[
+                    "delimiter",
+                    "tab",
+                    "regex_delim",
+                    "str_delim",
+                    "byte_list",
+                    "char_list",
+                    "head_fields",
+                ]),
+        )
+        .arg(
+            Arg::new("squeeze")
+                .long("squeeze")
+                .help("treat a run of delimiters as one, like awk does with whitespace")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(&["byte_list", "char_list"]),
+        )
+        .arg(
+            Arg::new("output_format")
+                .value_name("FORMAT")
+                .long("output-format")
+                .help("write the selected fields as delimited text or as a JSON object per line")
+                .takes_value(true)
+                .possible_values(["delimited", "json"])
+                .default_value("delimited")
+                .conflicts_with_all(&["byte_list", "char_list", "describe", "group_sum"]),
+        )
+        .arg(
+            Arg::new("output_delimiter")
+                .value_name("DELIM")
+                .long("output-delimiter")
+                .help("use DELIM to separate the selected fields, or regions of bytes or characters")
+                .takes_value(true)
+                .conflicts_with("join"),
+        )
+        .arg(
+            Arg::new("group_separator")
+                .value_name("STR")
+                .long("group-separator")
+                .help("separate each run of consecutive fields selected from the next with STR")
+                .takes_value(true)
+                .conflicts_with_all(&[
+                    "byte_list",
+                    "char_list",
+                    "output_format",
+                    "quote_all",
+                    "strict_csv",
+                    "raw",
+                ]),
+        )
+        .arg(
+            Arg::new("repeat")
+                .value_name("N")
+                .long("repeat")
+                .help("write out each selected field N times over, e.g. a,a,b,b for -f 1-2 --repeat 2")
+                .takes_value(true)
+                .conflicts_with_all(&[
+                    "byte_list",
+                    "char_list",
+                    "output_format",
+                    "color",
+                    "describe",
+                    "group_sum",
+                ]),
+        )
+        .arg(
+            Arg::new("tab_output")
+                .long("tab-output")
+                .help("use TAB to separate the selected fields, whatever the delimiter")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(&["output_delimiter", "join"]),
+        )
+        .arg(
+            Arg::new("fields")
+                .value_name("LIST")
+                .short('f')
+                .long("fields")
+                .help("select only these fields, which may be quoted as in CSV")
+                // So that a LIST like -5 isn't mistaken for a flag
+                .allow_hyphen_values(true)
+                .takes_value(true)
+                .conflicts_with_all(&["byte_list", "char_list"]),
+        )
+        .arg(
+            Arg::new("characters")
+                .value_name("LIST")
+                .short('c')
+                .long("characters")
+                .help("select only these characters")
+                .allow_hyphen_values(true)
+                .takes_value(true)
+                .conflicts_with_all(&["byte_list", "field_list", "delimiter"]),
+        )
+        .arg(
+            Arg::new("bytes")
+                .value_name("LIST")
+                .short('b')
+                .long("bytes")
+                .help("select only these bytes")
+                .allow_hyphen_values(true)
+                .multiple_values(false)
+                .conflicts_with_all(&["field_list", "char_list", "delimiter"]),
+        )
+        .arg(
+            Arg::new("fields_file")
+                .value_name("PATH")
+                .long("fields-file")
+                .help("select the fields listed in the file PATH, split by commas or whitespace")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("characters_file")
+                .value_name("PATH")
+                .long("characters-file")
+                .help("select the characters listed in the file PATH")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("bytes_file")
+                .value_name("PATH")
+                .long("bytes-file")
+                .help("select the bytes listed in the file PATH")
+                .takes_value(true),
+        )
+        // A LIST can be given inline or in a file but not both
+        .group(ArgGroup::new("field_list").args(&["fields", "fields_file"]))
+        .group(ArgGroup::new("char_list").args(&["characters", "characters_file"]))
+        .group(ArgGroup::new("byte_list").args(&["bytes", "bytes_file"]))
+        .arg(
+            Arg::new("interleave")
+                .long("interleave")
+                .help("cut line N of every FILE in turn before moving on to line N + 1")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("paste"),
+        )
+        .arg(
+            Arg::new("paste")
+                .long("paste")
+                .help("join the cut of line N of every FILE into a single output line")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("join")
+                .value_name("STR")
+                .long("join")
+                .help("separate disjoint regions of selected characters or bytes with STR")
+                .takes_value(true)
+                .conflicts_with("field_list"),
+        )
+        .arg(
+            Arg::new("pipe_to")
+                .value_name("CMD")
+                .long("pipe-to")
+                .help("write the output to the stdin of the shell command CMD")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("empty_report")
+                .long("empty-report")
+                .help("report on stderr how many records had each selected field empty")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(&["byte_list", "char_list"]),
+        )
+        .arg(
+            Arg::new("hash_fields")
+                .value_name("LIST")
+                .long("hash-fields")
+                .help("replace the values of these selected fields with a short hash")
+                .takes_value(true)
+                .requires("field_list"),
+        )
+        .arg(
+            Arg::new("output")
+                .value_name("OUT")
+                .short('o')
+                .long("output")
+                .help("write the output to the file OUT, replacing anything already in it")
+                .takes_value(true)
+                .conflicts_with_all(&["pipe_to", "split_output"]),
+        )
+        .arg(
+            Arg::new("split_output")
+                .value_name("PREFIX")
+                .long("split-output")
+                .help("deal the output lines out across the files PREFIX.0 to PREFIX.(N-1)")
+                .takes_value(true)
+                .requires("parts")
+                .conflicts_with("pipe_to"),
+        )
+        .arg(
+            Arg::new("parts")
+                .value_name("N")
+                .long("parts")
+                .help("the number of files to split the output across")
+                .takes_value(true)
+                .requires("split_output"),
+        )
+        .arg(
+            Arg::new("headers_only")
+                .long("headers-only")
+                .help("print only the selected fields of the first (header) line of each FILE")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(&["byte_list", "char_list"]),
+        )
+        .arg(
+            Arg::new("sort_by")
+                .value_name("KEYS")
+                .long("sort-by")
+                .help("sort the records by these fields, e.g. '2n,1r' (n: numeric, r: reverse)")
+                .takes_value(true)
+                .conflicts_with_all(&["byte_list", "char_list"]),
+        )
+        .arg(
+            Arg::new("detect_encoding")
+                .long("detect-encoding")
+                .help("only report the likely encoding of each FILE on stderr")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("encoding")
+                .value_name("ENCODING")
+                .long("encoding")
+                .help("decode the input from ENCODING into UTF-8, dropping any BOM, before cutting it")
+                .takes_value(true)
+                .possible_values(["utf-8", "utf-16le", "utf-16be"])
+                .conflicts_with_all(&["detect_encoding", "bytes_total"]),
+        )
+        .arg(
+            Arg::new("decompress")
+                .value_name("FORMAT")
+                .long("decompress")
+                .help(
+                    "decompress all the input, stdin included, rather than only FILEs ending in \
+                     .gz, .zst or .bz2",
+                )
+                .takes_value(true)
+                .possible_values(["gz", "zstd", "bz2"]),
+        )
+        .arg(
+            Arg::new("show_ranges")
+                .long("show-ranges")
+                .help("only print the 0-based ranges of indices LIST comes down to, reading no input")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(&["stats", "detect_encoding"]),
+        )
+        .arg(
+            Arg::new("color")
+                .value_name("WHEN")
+                .long("color")
+                .help("print each line whole with the selected fields coloured in, if WHEN allows")
+                .takes_value(true)
+                .possible_values(["auto", "always", "never"])
+                .conflicts_with_all(&[
+                    "byte_list",
+                    "char_list",
+                    "regex_delim",
+                    "str_delim",
+                    "head_fields",
+                    "squeeze",
+                    "output_format",
+                    "describe",
+                    "group_sum",
+                    "from_ndjson",
+                    "header_names",
+                    "count_fields",
+                ]),
+        )
+        .arg(
+            Arg::new("with_filename")
+                .short('H')
+                .long("with-filename")
+                .help("head the output of each FILE with its name, as is done if there are many (to stdout)")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(&[
+                    "no_filename",
+                    "interleave",
+                    "paste",
+                    "bytes_total",
+                    "split_output",
+                ]),
+        )
+        .arg(
+            Arg::new("no_filename")
+                .long("no-filename")
+                .help("cut many FILEs one after the other with no name heading each")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("stats")
+                .long("stats")
+                .help("report the lines read, written and skipped and the bytes written on stderr")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(&["detect_encoding", "follow"]),
+        )
+        .arg(
+            Arg::new("progress")
+                .long("progress")
+                .help("keep showing how many lines and bytes have been read on stderr, if it's a TTY")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("detect_encoding"),
+        )
+        .arg(
+            Arg::new("follow")
+                .long("follow")
+                .help("keep cutting lines as they are appended to FILE, like tail -f")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(&["interleave", "paste", "sort_by", "describe"]),
+        )
+        .arg(
+            Arg::new("no_quoting")
+                .long("no-quoting")
+                .help("take quotes literally instead of as quoting fields, and add none to the output")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(&["byte_list", "char_list", "quote_all"]),
+        )
+        .arg(
+            Arg::new("quote_all")
+                .long("quote-all")
+                .help("quote every output field, whether or not it needs it")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(&["byte_list", "char_list"]),
+        )
+        .arg(
+            Arg::new("strict_csv")
+                .long("strict-csv")
+                .help("quote output fields as RFC 4180 says, so they read back as they were")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(&[
+                    "byte_list",
+                    "char_list",
+                    "quote_all",
+                    "no_quoting",
+                    "head_fields",
+                    "regex_delim",
+                    "str_delim",
+                    "output_format",
+                ]),
+        )
+        .arg(
+            Arg::new("raw")
+                .long("raw")
+                .help("never quote output fields, even those it would take to read them back")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(&["byte_list", "char_list", "quote_all", "strict_csv"]),
+        )
+        .arg(
+            Arg::new("max_output_bytes")
+                .value_name("N")
+                .long("max-output-bytes")
+                .help("stop once N bytes of output have been written")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("whole_lines")
+                .long("whole-lines")
+                .help("never cut off the last line written before the --max-output-bytes limit")
+                .action(ArgAction::SetTrue)
+                .requires("max_output_bytes"),
+        )
+        .arg(
+            Arg::new("write_bom")
+                .long("write-bom")
+                .help("start the output with a UTF-8 byte order mark, as Excel likes")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("dos2unix")
+                .long("dos2unix")
+                .help("end every output line with LF, even where the input used CRLF")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("unix2dos"),
+        )
+        .arg(
+            Arg::new("no_split")
+                .short('n')
+                .long("no-split")
+                .help("with -b, don't split multibyte characters")
+                .action(ArgAction::SetTrue)
+                .requires("byte_list"),
+        )
+        .arg(
+            Arg::new("char_safe_bytes")
+                .long("char-safe-bytes")
+                .help("with -b, write out the whole of any multibyte character partly selected")
+                .action(ArgAction::SetTrue)
+                .requires("byte_list")
+                .conflicts_with("no_split"),
+        )
+        .arg(
+            Arg::new("strict_utf8")
+                .long("strict-utf8")
+                .help("with -b, fail rather than write out bytes which aren't valid UTF-8")
+                .action(ArgAction::SetTrue)
+                .requires("byte_list"),
+        )
+        .arg(
+            Arg::new("hex")
+                .long("hex")
+                .help("with -b, write out the selected bytes in hex, e.g. '61 62'")
+                .action(ArgAction::SetTrue)
+                .requires("byte_list")
+                .conflicts_with_all(&["strict_utf8", "join", "output_delimiter"]),
+        )
+        .arg(
+            Arg::new("zero_terminated")
+                .short('z')
+                .long("zero-terminated")
+                .help("line delimiter is NUL, not newline")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("unix2dos"),
+        )
+        .arg(
+            Arg::new("null_output")
+                .long("null-output")
+                .help("end every output line with NUL, however the input lines end")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("unix2dos"),
+        )
+        .arg(
+            Arg::new("keep_cr")
+                .long("keep-cr")
+                .help("with -b or -c, keep the CR on the end of lines ending in CRLF")
+                .action(ArgAction::SetTrue)
+                // A CR kept in a field would have to be quoted, which is no use to anyone
+                .conflicts_with_all(&["field_list", "from_ndjson", "dos2unix"]),
+        )
+        .arg(
+            Arg::new("output_newline")
+                .value_name("NEWLINE")
+                .long("output-newline")
+                .help("end every output line with NEWLINE, LF or CRLF")
+                .takes_value(true)
+                .possible_values(["lf", "crlf"])
+                .conflicts_with_all(&["zero_terminated", "null_output", "unix2dos", "dos2unix"]),
+        )
+        .arg(
+            Arg::new("unix2dos")
+                .long("unix2dos")
+                .help("end every output line with CRLF")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("bytes_total")
+                .long("bytes-total")
+                .help("only print the number of bytes in each FILE, like wc -c")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("jobs")
+                .value_name("N")
+                .long("jobs")
+                .help("cut up to N files at once, still writing them out in order")
+                .takes_value(true)
+                .default_value("1")
+                .conflicts_with_all(&["interleave", "paste", "follow"]),
+        )
+        .arg(
+            Arg::new("keep_going")
+                .long("keep-going")
+                .help("skip any line which can't be read or split up, with a warning, rather than failing")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("min_length")
+                .value_name("N")
+                .long("min-length")
+                .help("skip lines shorter than N bytes with -b, or N characters with -c")
+                .takes_value(true)
+                .conflicts_with("field_list"),
+        )
+        .arg(
+            Arg::new("skip_lines")
+                .value_name("N")
+                .long("skip-lines")
+                .help("skip the first N lines of each FILE, before even its header")
+                .takes_value(true)
+                .conflicts_with_all(&["bytes_total", "detect_encoding", "follow"]),
+        )
+        .arg(
+            Arg::new("max_lines")
+                .value_name("N")
+                .short('m')
+                .long("max-lines")
+                .help("stop after writing out N lines of each FILE")
+                .takes_value(true)
+                .conflicts_with_all(&["interleave", "paste", "follow"]),
+        )
+        .arg(
+            Arg::new("retry_on_lock")
+                .value_name("N")
+                .long("retry-on-lock")
+                .help("retry opening a FILE up to N times while another process has it locked")
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::new("head_fields")
+                .value_name("N")
+                .long("head-fields")
+                .help(
+                    "split off only the first N fields, keeping the rest of the line as one field",
+                )
+                .takes_value(true)
+                .conflicts_with_all(&["byte_list", "char_list", "describe"]),
+        )
+        .arg(
+            Arg::new("null_ratio")
+                .long("null-ratio")
+                .help("report on stderr the percentage of records with each selected field empty")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(&["byte_list", "char_list"]),
+        )
+        .arg(
+            Arg::new("from_ndjson")
+                .long("from-ndjson")
+                .help(
+                    "read each line as a JSON object and select its values by the keys in --fields",
+                )
+                .action(ArgAction::SetTrue)
+                .requires("field_list")
+                .conflicts_with_all(&[
+                    "describe",
+                    "sort_by",
+                    "head_fields",
+                    "hash_fields",
+                    "headers_only",
+                    "empty_report",
+                    "null_ratio",
+                ]),
+        )
+        .arg(
+            Arg::new("missing_as")
+                .value_name("STR")
+                .long("missing-as")
+                .help("the value to write for keys missing from an NDJSON line")
+                .takes_value(true)
+                .default_value("")
+                .requires("from_ndjson"),
+        )
+        .arg(
+            Arg::new("pretty_errors")
+                .long("pretty-errors")
+                .help("quote the offending line, pointing out the problem, on bad input")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("group_sum")
+                .long("group-sum")
+                .help("sum the second selected field for each distinct value of the first")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(&["byte_list", "char_list", "describe", "sort_by"]),
+        )
+        .arg(
+            Arg::new("hash_sample")
+                .value_name("FIELD:FRACTION")
+                .long("hash-sample")
+                .help("keep the records where a hash of FIELD falls below FRACTION, e.g. '1:0.1'")
+                .takes_value(true)
+                .conflicts_with_all(&["byte_list", "char_list", "describe"]),
+        )
+        .arg(
+            Arg::new("keep_order")
+                .long("keep-order")
+                .help("print the selection in the order it was listed, e.g. '-f 3,1'")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(&["describe", "group_sum"]),
+        )
+        .arg(
+            Arg::new("reverse")
+                .long("reverse")
+                .help("print the selected fields last to first, e.g. '-f 1-3' as 3,2,1")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(&["byte_list", "char_list", "keep_order", "describe", "group_sum"]),
+        )
+        .arg(
+            Arg::new("header")
+                .long("header")
+                .help("always print the first line, cut like the rest, even if -s or --sort-by would drop or move it")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(&["byte_list", "char_list", "from_ndjson"]),
+        )
+        .arg(
+            Arg::new("header_regex")
+                .value_name("PATTERN")
+                .long("header-regex")
+                .help("select the fields whose names, by the first line of each FILE, match PATTERN")
+                .takes_value(true)
+                .conflicts_with_all(&[
+                    "field_list",
+                    "byte_list",
+                    "char_list",
+                    "header_names",
+                    "from_ndjson",
+                    "group_sum",
+                    "interleave",
+                    "paste",
+                    "tail_anchored",
+                ]),
+        )
+        .arg(
+            Arg::new("header_names")
+                .long("header-names")
+                .help("select the fields named (or globbed) in --fields, by the first line of each FILE")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(&[
+                    "byte_list",
+                    "char_list",
+                    "from_ndjson",
+                    "group_sum",
+                    "interleave",
+                    "paste",
+                ]),
+        )
+        .arg(
+            Arg::new("default")
+                .value_name("NAME=VALUE")
+                .long("default")
+                .help("fill in the field NAME with VALUE in any FILE whose header hasn't got it")
+                .takes_value(true)
+                .action(ArgAction::Append)
+                .requires("header_names")
+                .conflicts_with_all(&["only_delimited", "squeeze"]),
+        )
+        .arg(
+            Arg::new("line_numbers")
+                .short('N')
+                .long("line-numbers")
+                .help("prefix each line with the number of the line it was cut from in its FILE")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(&[
+                    "interleave",
+                    "paste",
+                    "describe",
+                    "group_sum",
+                    "from_ndjson",
+                ]),
+        )
+        .arg(
+            Arg::new("strict_ranges")
+                .long("strict-ranges")
+                .help("fail on any line too short for all of LIST, rather than cutting what's there")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("zero_based")
+                .long("zero-based")
+                .help("count the positions in LIST (and --hash-fields) from 0 rather than 1")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("tail_anchored")
+                .long("tail-anchored")
+                .help("count positions in LIST like -2 back from the end of each line, -1 being the last")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(&[
+                    "describe",
+                    "group_sum",
+                    "empty_report",
+                    "null_ratio",
+                    "from_ndjson",
+                    "header_names",
+                ]),
+        )
+        .arg(
+            Arg::new("files_from")
+                .value_name("PATH")
+                .long("files-from")
+                .help("also cut the files named one per line in PATH, or in stdin if PATH is -")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("recursive")
+                .short('r')
+                .long("recursive")
+                .help("cut every file under any FILE which is a directory")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("only_delimited")
+                .short('s')
+                .long("only-delimited")
+                .help("do not print lines not containing delimiters")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(&["byte_list", "char_list"]),
+        )
+        .arg(
+            Arg::new("na")
+                .value_name("STR")
+                .long("na")
+                .help("write STR in place of any selected field which is empty, e.g. NA or \\N")
+                .takes_value(true)
+                .conflicts_with_all(&["byte_list", "char_list"]),
+        )
+        .arg(
+            Arg::new("trim")
+                .long("trim")
+                .help("strip the whitespace from around each field before writing it out")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(&["byte_list", "char_list"]),
+        )
+        .arg(
+            Arg::new("count_fields")
+                .long("count-fields")
+                .help("print the number of fields on each line instead of cutting them")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(&[
+                    "byte_list",
+                    "char_list",
+                    "describe",
+                    "group_sum",
+                    "from_ndjson",
+                    "header_names",
+                ]),
+        )
+        .arg(
+            Arg::new("describe")
+                .long("describe")
+                .help("print the inferred type, null count and distinct values of each field")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(&["byte_list", "char_list", "interleave", "paste"]),
+        )
+        .arg(
+            Arg::new("sample")
+                .value_name("N")
+                .long("sample")
+                .help("describe the fields using only the first N records")
+                .takes_value(true)
+                .default_value("100"),
+        )
+        .arg(
+            Arg::new("files")
+                .value_name("FILE")
+                .help("the files to cut, in the order given, reading stdin for each - among them")
+                .takes_value(true)
+                .multiple_values(true)
+                .default_value("-"),
+        )
+        .arg(
+            Arg::new("config")
+                .value_name("FILE")
+                .long("config")
+                .help("read default option values from the JSON object in FILE")
+                .takes_value(true),
+        );
+    let matches = app.clone().get_matches_from(cmd_args);
+    let recipe = match matches.get_one::<String>("config") {
+        Some(path) => load_recipe(path, &app)?,
+        None => Recipe::new(),
+    };
+    let args = Args {
+        matches: &matches,
+        recipe,
+    };
+
+    let detect_encoding = args.flag("detect_encoding");
+    let bytes_total = args.flag("bytes_total");
+    let count_fields = args.flag("count_fields");
+    let selector = match parse_selector(&args) {
+        // These never get as far as selecting anything, so it's fine to go without a selector
+        Err(_) if detect_encoding || bytes_total => Selector::Bytes(vec![]),
+        Err(_) if count_fields => Selector::Fields(vec![]),
+        result => result?,
+    };
+    // Cutting bytes or chars, the output delimiter goes between the regions picked out rather
+    // than fields, so it can be any string
+    let slicing = matches!(selector, Selector::Bytes(_) | Selector::Chars(_));
+    let output_delimiter = match args.flag("tab_output") {
+        true => Some("\t".to_owned()),
+        false => args.string("output_delimiter"),
+    };
+    // And cutting fields, one longer than a byte is joined up by hand rather than by the csv writer
+    let long_output_delimiter = output_delimiter
+        .as_ref()
+        .map(|output_delimiter| unescape(output_delimiter))
+        .filter(|output_delimiter| !slicing && output_delimiter.len() > 1);
+    let join = match (args.string("join"), &output_delimiter) {
+        (Some(join), _) => Some(join),
+        (None, Some(output_delimiter)) if slicing => Some(unescape(output_delimiter)),
+        (None, _) => None,
+    };
+    let delim_char = args
+        .string("delim_char")
+        .map(|delim| {
+            let delim = unescape(&delim);
+            let mut chars = delim.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Ok(c),
+                _ => Err(format!("kat: bad delimiter character: '{}'", delim)),
+            }
+        })
+        .transpose()?;
+    // The csv reader only splits on a byte, so any other character is split on as a string
+    let delimiter = match (args.flag("tab"), delim_char) {
+        (true, _) => "\t".to_owned(),
+        (false, Some(c)) if c.is_ascii() => c.to_string(),
+        (false, _) => args.string("delimiter").unwrap(),
+    };
+    let mut builder = ConfigBuilder::new()
+        .files(args.strings("files"))
+        .selector(selector)
+        .delimiter(&delimiter);
+    if let Some(output_delimiter) = output_delimiter
+        .as_ref()
+        .filter(|_| !slicing && long_output_delimiter.is_none())
+    {
+        builder = builder.output_delimiter(output_delimiter);
+    }
+    // The rest of the options are filled in on top of what the builder checked
+    let mut base = builder.build()?;
+    if let Some(list) = args.string("files_from") {
+        // The listed files take over from stdin, which is only there as the default
+        let given = args.on_cli("files") || args.recipe.contains_key("files");
+        if !given {
+            base.files.clear();
+        }
+        if list == "-" && base.files.iter().any(|file| file == "-") {
+            return Err("kat: can't read both the file list and the data from stdin".into());
+        }
+        base.files.extend(read_file_list(&list)?);
+    }
+    if args.flag("recursive") {
+        base.files = expand_dirs(base.files)?;
+    }
+
+    let regex_delim = args
+        .string("regex_delim")
+        .map(|pattern| {
+            Regex::new(&pattern).map_err(|e| format!("kat: invalid regex delimiter: {}", e))
+        })
+        .transpose()?;
+
+    let str_delim = args
+        .string("str_delim")
+        .map(|delim| unescape(&delim))
+        .or_else(|| delim_char.filter(|c| !c.is_ascii()).map(String::from));
+    if str_delim.as_deref() == Some("") {
+        return Err("kat: bad string delimiter".into());
+    }
+    // The fields are joined back up with the same string unless we're told otherwise
+    let joiner = match (&output_delimiter, long_output_delimiter) {
+        (_, Some(long_output_delimiter)) => Some(long_output_delimiter),
+        (Some(output_delimiter), None) => str_delim.as_ref().map(|_| unescape(output_delimiter)),
+        (None, None) => str_delim.clone(),
+    };
+
+    let output_format = match args.string("output_format").as_deref() {
+        Some("json") => OutputFormat::Json,
+        _ => OutputFormat::Delimited,
+    };
+    if cfg!(not(feature = "ndjson")) && output_format == OutputFormat::Json {
+        return Err(
+            "kat: --output-format json requires kat to be built with the 'ndjson' feature".into(),
+        );
+    }
+    if output_format == OutputFormat::Json && args.flag("with_filename") {
+        return Err("kat: --with-filename can't be used with --output-format json".into());
+    }
+    // Like grep, there's a banner for each file by default only when there's more than one, and
+    // then only in plain delimited output to stdout, where nothing else is made of the lines
+    let with_filename = args.flag("with_filename")
+        || (base.files.len() > 1
+            && !args.flag("no_filename")
+            && output_format == OutputFormat::Delimited
+            && ["output", "pipe_to", "split_output"]
+                .iter()
+                .all(|id| args.string(id).is_none())
+            && !args.flag("interleave")
+            && !args.flag("paste"));
+
+    let hash_fields = args
+        .string("hash_fields")
+        .map(|s| parse_positions(s.as_str(), args.flag("zero_based")))
+        .transpose()?;
+    if cfg!(not(feature = "hash")) && hash_fields.is_some() {
+        return Err("kat: --hash-fields requires kat to be built with the 'hash' feature".into());
+    }
+
+    let encoding = args.string("encoding");
+    if cfg!(not(feature = "encoding")) && encoding.is_some() {
+        return Err("kat: --encoding requires kat to be built with the 'encoding' feature".into());
+    }
+
+    // Like grep, colour is left off where it might end up in a file or the next program's input,
+    // or when it's asked to be with NO_COLOR
+    let color = args.string("color").map(|when| match when.as_str() {
+        "always" => true,
+        "never" => false,
+        _ => stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty()),
+    });
+
+    let decompress = args
+        .string("decompress")
+        .map(|format| match format.as_str() {
+            "zstd" => Compression::Zstd,
+            "bz2" => Compression::Bzip2,
+            _ => Compression::Gzip,
+        });
+    if let Some(compression) = decompress.filter(|compression| !compression.built_in()) {
+        return Err(format!(
+            "kat: --decompress {} requires kat to be built with the '{}' feature",
+            args.string("decompress").unwrap(),
+            compression.feature()
+        )
+        .into());
+    }
+
+    let follow = args.flag("follow");
+    if follow && (base.files.len() != 1 || base.files[0] == "-") {
+        return Err("kat: --follow needs exactly one FILE, which can't be stdin".into());
+    }
+
+    // The size of the FILEs only goes for what's read out of them if it's read as it is
+    let progress = (args.flag("progress") && stderr().is_terminal()).then(|| {
+        let total_bytes = match decompress.is_some() || encoding.is_some() {
+            true => None,
+            false => total_size(&base.files),
+        };
+        Arc::new(Progress::new(total_bytes, Box::new(stderr())))
+    });
+
+    let max_output_bytes = args
+        .string("max_output_bytes")
+        .map(|s| {
+            s.parse()
+                .map_err(|_| format!("kat: invalid byte count: '{}'", s))
+        })
+        .transpose()?;
+
+    let quoting = if args.flag("quote_all") {
+        Quoting::Always
+    } else if args.flag("strict_csv") {
+        Quoting::Strict
+    } else if args.flag("raw") {
+        Quoting::Never
+    } else {
+        Quoting::Necessary
+    };
+
+    let zero_terminated = args.flag("zero_terminated");
+    // Lines are read the same way whatever they end with, so --dos2unix is the default really
+    let terminator = if zero_terminated || args.flag("null_output") {
+        Terminator::Nul
+    } else if args.flag("unix2dos") || args.string("output_newline").as_deref() == Some("crlf") {
+        Terminator::CrLf
+    } else {
+        Terminator::Lf
+    };
+
+    let retry_on_lock = args.string("retry_on_lock").unwrap();
+    let retry_on_lock = retry_on_lock
+        .parse()
+        .map_err(|_| format!("kat: invalid retry count: '{}'", retry_on_lock))?;
+
+    // The key and value fields are taken in the order they were listed rather than sorted
+    let group_sum =
+        match (&base.selector, args.flag("group_sum")) {
+            (Selector::Fields(positions), true) => {
+                // An open-ended range can never be exactly two fields (nor should it be expanded)
+                let mut fields = match positions.iter().any(|r| r.end == OPEN_END) {
+                    true => vec![],
+                    false => positions.iter().flat_map(|r| r.clone()).collect::<Vec<_>>(),
+                };
+                fields.dedup();
+                match fields[..] {
+                    [key, value] => Some((key, value)),
+                    _ => return Err(
+                        "kat: --group-sum needs exactly two fields: the key, then the value to sum"
+                            .into(),
+                    ),
+                }
+            }
+            _ => None,
+        };
+
+    let hash_sample = args
+        .string("hash_sample")
+        .map(|s| parse_hash_sample(s.as_str()))
+        .transpose()?;
+    if cfg!(not(feature = "hash")) && hash_sample.is_some() {
+        return Err("kat: --hash-sample requires kat to be built with the 'hash' feature".into());
+    }
+
+    let head_fields = args
+        .string("head_fields")
+        .map(|s| match s.parse() {
+            Ok(n) if n > 0 => Ok(n),
+            _ => Err(format!("kat: invalid number of fields: '{}'", s)),
+        })
+        .transpose()?;
+
+    let sort_by = args
+        .string("sort_by")
+        .map(|s| parse_sort_keys(s.as_str()))
+        .transpose()?;
+
+    let split_output = match args.string("split_output") {
+        Some(prefix) => {
+            let parts = args.string("parts").unwrap_or_default();
+            match parts.parse() {
+                Ok(n) if n > 0 => Some((prefix, n)),
+                _ => return Err(format!("kat: invalid number of parts: '{}'", parts).into()),
+            }
+        }
+        None => None,
+    };
+
+    let jobs = args.string("jobs").unwrap();
+    let jobs = match jobs.parse() {
+        Ok(n) if n > 0 => n,
+        _ => return Err(format!("kat: invalid number of jobs: '{}'", jobs).into()),
+    };
+
+    let max_lines = args
+        .string("max_lines")
+        .map(|max| {
+            max.parse()
+                .map_err(|_| format!("kat: invalid number of lines: '{}'", max))
+        })
+        .transpose()?;
+
+    let skip_lines = args
+        .string("skip_lines")
+        .map(|n| {
+            n.parse()
+                .map_err(|_| format!("kat: invalid number of lines: '{}'", n))
+        })
+        .transpose()?
+        .unwrap_or(0);
+
+    let defaults = args
+        .strings("default")
+        .into_iter()
+        .map(|default| match default.split_once('=') {
+            Some((name, value)) if !name.is_empty() => Ok((name.to_owned(), value.to_owned())),
+            _ => Err(format!(
+                "kat: bad default: '{}' (it has to be NAME=VALUE)",
+                default
+            )),
+        })
+        .collect::<Result<_, _>>()?;
+
+    let repeat = match args.string("repeat") {
+        Some(n) => match n.parse() {
+            Ok(n) if n >= 1 => n,
+            _ => return Err(format!("kat: invalid repeat count: '{}'", n).into()),
+        },
+        None => 1,
+    };
+
+    let min_length = args
+        .string("min_length")
+        .map(|min| {
+            min.parse()
+                .map_err(|_| format!("kat: invalid length: '{}'", min))
+        })
+        .transpose()?
+        .unwrap_or(0);
+
+    let sample = args.string("sample").unwrap();
+    let sample = sample
+        .parse()
+        .map_err(|_| format!("kat: invalid sample size: '{}'", sample))?;
+
+    let config = Config {
+        interleave: args.flag("interleave"),
+        paste: args.flag("paste"),
+        describe: args.flag("describe"),
+        join,
+        pipe_to: args.string("pipe_to"),
+        empty_report: args.flag("empty_report"),
+        hash_fields,
+        split_output,
+        headers_only: args.flag("headers_only"),
+        sort_by,
+        detect_encoding,
+        encoding,
+        stats: args.flag("stats").then(Arc::default),
+        progress,
+        decompress,
+        show_ranges: args.flag("show_ranges"),
+        color,
+        with_filename,
+        max_lines,
+        keep_going: args.flag("keep_going"),
+        hex: args.flag("hex"),
+        na: args.string("na"),
+        min_length,
+        char_safe_bytes: args.flag("char_safe_bytes"),
+        group_separator: args.string("group_separator").map(|sep| unescape(&sep)),
+        repeat,
+        skip_lines,
+        defaults,
+        follow,
+        quoting,
+        max_output_bytes,
+        whole_lines: args.flag("whole_lines"),
+        write_bom: args.flag("write_bom"),
+        terminator,
+        bytes_total,
+        retry_on_lock,
+        head_fields,
+        null_ratio: args.flag("null_ratio"),
+        missing_as: args.string("missing_as").unwrap(),
+        pretty_errors: args.flag("pretty_errors"),
+        group_sum,
+        hash_sample,
+        only_delimited: args.flag("only_delimited"),
+        trim: args.flag("trim"),
+        header: args.flag("header"),
+        keep_order: args.flag("keep_order"),
+        reverse: args.flag("reverse"),
+        zero_terminated,
+        no_split: args.flag("no_split"),
+        regex_delim,
+        str_delim,
+        joiner,
+        squeeze: args.flag("squeeze"),
+        output_format,
+        line_numbers: args.flag("line_numbers"),
+        keep_cr: args.flag("keep_cr"),
+        no_quoting: args.flag("no_quoting"),
+        jobs,
+        strict_utf8: args.flag("strict_utf8"),
+        output: args.string("output"),
+        count_fields,
+        strict_ranges: args.flag("strict_ranges"),
+        zero_based: args.flag("zero_based"),
+        tail_anchored: args.flag("tail_anchored"),
+        sample,
+        ..base
+    };
+    Ok(config)
+}
+
+/// Option values read from a `--config` file, keyed by argument id.
+type Recipe = HashMap<String, RecipeValue>;
+
+#[derive(Debug)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(untagged))]
+#[cfg_attr(not(feature = "config"), allow(dead_code))]
+enum RecipeValue {
+    Flag(bool),
+    Number(u64),
+    Text(String),
+    List(Vec<String>),
+}
+
+impl RecipeValue {
+    fn to_strings(&self) -> Vec<String> {
+        match self {
+            RecipeValue::Flag(b) => vec![b.to_string()],
+            RecipeValue::Number(n) => vec![n.to_string()],
+            RecipeValue::Text(s) => vec![s.to_owned()],
+            RecipeValue::List(items) => items.clone(),
+        }
+    }
+}
+
+/// The config file is a JSON object whose keys are the long names of our options, e.g.
+/// `{"fields": "1,3", "delimiter": ",", "files": ["a.csv", "b.csv"]}`.
+#[cfg(feature = "config")]
+fn load_recipe(path: &str, app: &App) -> CliResult<Recipe> {
+    let contents = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let entries: HashMap<String, RecipeValue> = serde_json::from_str(&contents)
+        .map_err(|e| format!("kat: bad config file '{}': {}", path, e))?;
+
+    let mut recipe = Recipe::new();
+    for (key, value) in entries {
+        let id = key.replace('-', "_");
+        if id == "config" || !app.get_arguments().any(|arg| arg.get_id() == id) {
+            return Err(format!("kat: unknown option '{}' in config file '{}'", key, path).into());
+        }
+        recipe.insert(id, value);
+    }
+    Ok(recipe)
+}
+
+#[cfg(not(feature = "config"))]
+fn load_recipe(_path: &str, _app: &App) -> CliResult<Recipe> {
+    Err("kat: --config requires kat to be built with the 'config' feature".into())
+}
+
+/// Looks up option values on the command line first, falling back to the config file and only
+/// then to the defaults declared on the args themselves.
+struct Args<'a> {
+    matches: &'a ArgMatches,
+    recipe: Recipe,
+}
+
+impl Args<'_> {
+    fn on_cli(&self, id: &str) -> bool {
+        self.matches.value_source(id) == Some(ValueSource::CommandLine)
+    }
+
+    fn strings(&self, id: &str) -> Vec<String> {
+        match self.recipe.get(id) {
+            Some(value) if !self.on_cli(id) => value.to_strings(),
+            _ => self
+                .matches
+                .get_many::<String>(id)
+                .map(|vals| vals.cloned().collect())
+                .unwrap_or_default(),
+        }
+    }
+
+    fn string(&self, id: &str) -> Option<String> {
+        self.strings(id).into_iter().next()
+    }
+
+    fn flag(&self, id: &str) -> bool {
+        match self.recipe.get(id) {
+            Some(RecipeValue::Flag(b)) if !self.on_cli(id) => *b,
+            _ => *self.matches.get_one::<bool>(id).unwrap(),
+        }
+    }
+}
+
+/// Parses a list of positions, sorting them and merging any which overlap or touch so that, for
+/// instance, `1-3,2-5` is the same as `1-5`.
+fn parse_positions(arg: &str, zero_based: bool) -> CliResult<Positions> {
+    Ok(merge_ranges(&parse_listed_positions(arg, zero_based)?))
+}
+
+/// Parses a list of positions, keeping them in the order they were listed. The positions are
+/// counted from 1 unless they're `zero_based`. A closed range may end in a step, e.g. `1-9:2`
+/// for every other position from 1 to 9, which is expanded into the positions it stands for
+/// (so long as there aren't more than `MAX_STEPPED` of them). A lone `*` stands for every
+/// position.
+fn parse_listed_positions(arg: &str, zero_based: bool) -> CliResult<Positions> {
+    let func = |v: &str| KatError::IllegalListValue(v.to_owned());
+    if arg.is_empty() || arg.starts_with(',') || arg.ends_with(',') {
+        return Err(func(arg));
+    }
+    if arg == "*" {
+        return Ok(std::iter::once(0..OPEN_END).collect());
+    }
+    if arg.split(',').any(|part| part == "*") {
+        return Err(ALL_ALONE.into());
+    }
+
+    let first = if zero_based { 0 } else { 1 };
+    // Turns a position into the index it stands for, which mustn't be mistaken for one counting
+    // back from the end
+    let parse_num = |s: &str| -> CliResult<usize> {
+        if s.starts_with('-') || s.starts_with('+') || s.ends_with('-') || s.ends_with('+') {
+            return Err(func(s));
+        }
+        let num: usize = s.parse().map_err(|_| func(s))?;
+        if num < first {
+            Err(KatError::ZeroInList)
+        } else if num - first >= FROM_END {
+            Err(too_large(s))
+        } else {
+            Ok(num - first)
+        }
+    };
+    // The index just past `idx`, where a range ending at it stops
+    let past = |idx: usize| {
+        idx.checked_add(1)
+            .ok_or_else(|| too_large(&idx.to_string()))
+    };
+
+    let mut positions = vec![];
+    for part in arg.split(',') {
+        let (range, step) = match part.split_once(':') {
+            Some((range, step)) => (range, Some(step)),
+            None => (part, None),
+        };
+        let inner_parts = range.split('-').collect::<Vec<_>>();
+        if let Some(step) = step {
+            let step: usize = match step.parse() {
+                Ok(step) if step >= 1 => step,
+                _ => return Err(format!("kat: illegal step: '{}'", part).into()),
+            };
+            // Only a range with both ends can be stepped through
+            let (start, end) = match inner_parts[..] {
+                [start, end] if !start.is_empty() && !end.is_empty() => {
+                    (parse_num(start)?, parse_num(end)?)
+                }
+                _ => return Err(func(part)),
+            };
+            if end <= start {
+                return Err(KatError::EmptyRange {
+                    start: start + first,
+                    end: end + first,
+                });
+            }
+            if step == 1 {
+                positions.push(start..past(end)?);
+            } else if (end - start) / step >= MAX_STEPPED {
+                return Err(format!(
+                    "kat: too many positions in '{}' (a stepped range can't pick out more than {})",
+                    part, MAX_STEPPED
+                )
+                .into());
+            } else {
+                positions.extend((start..=end).step_by(step).map(|n| n..n + 1));
+            }
+            continue;
+        }
+        match inner_parts[..] {
+            [n] => {
+                let n: usize = parse_num(n)?;
+                positions.push(n..past(n)?);
+            }
+            ["", ""] => return Err(func(part)),
+            // Open-ended on the right, e.g. 3- which runs up to the end of the line
+            [start, ""] => {
+                let start: usize = parse_num(start)?;
+                positions.push(start..OPEN_END);
+            }
+            // Open-ended on the left, e.g. -5 which is the same as 1-5
+            ["", end] => {
+                let end: usize = parse_num(end)?;
+                positions.push(0..past(end)?);
+            }
+            [start, end] => {
+                let start: usize = parse_num(start)?;
+                let end: usize = parse_num(end)?;
+                if end <= start {
+                    return Err(KatError::EmptyRange {
+                        start: start + first,
+                        end: end + first,
+                    });
+                }
+                positions.push(start..past(end)?);
+            }
+            _ => return Err(func(part)),
+        }
+    }
+    Ok(positions)
+}
+
+/// The most positions a stepped range may be expanded into.
+const MAX_STEPPED: usize = 1 << 20;
+
+/// A position too far along to be told apart from those counting back from the end.
+fn too_large(position: &str) -> KatError {
+    format!("kat: position too large: '{}'", position).into()
+}
+
+const ALL_ALONE: &str = "kat: '*' can't be listed along with other positions";
+
+/// Parses a list of positions where those like `-2` count back from the end of the line rather
+/// than being the same as `1-2`. Unless they're to be kept in the order they were `listed`, the
+/// rest are merged as with `parse_positions` and those counting back put after them, as they
+/// can't be merged in until we know where they are.
+fn parse_anchored_positions(arg: &str, zero_based: bool, listed: bool) -> CliResult<Positions> {
+    if arg != "*" && arg.split(',').any(|part| part == "*") {
+        return Err(ALL_ALONE.into());
+    }
+    let mut positions = vec![];
+    for part in arg.split(',') {
+        match part.strip_prefix('-') {
+            Some(back) => match back.parse::<usize>() {
+                Ok(n) if n > FROM_END => return Err(too_large(part)),
+                Ok(n) if n >= 1 && !back.starts_with('+') => {
+                    positions.push(FROM_END + n - 1..FROM_END + n)
+                }
+                _ => return Err(KatError::IllegalListValue(part.to_owned())),
+            },
+            None => positions.extend(parse_listed_positions(part, zero_based)?),
+        }
+    }
+    if !listed {
+        let (back, rest): (Positions, Positions) = positions
+            .into_iter()
+            .partition(|r| from_end(r.start).is_some());
+        positions = merge_ranges(&rest);
+        positions.extend(back);
+    }
+    Ok(positions)
+}
+
+/// Expands the escape sequences `\t`, `\n`, `\0` and `\\` for the sake of shells (and config
+/// files) which make it awkward to pass the real thing. Any other backslash is left alone.
+fn unescape(arg: &str) -> String {
+    let mut unescaped = String::with_capacity(arg.len());
+    let mut chars = arg.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => unescaped.push('\t'),
+            Some('n') => unescaped.push('\n'),
+            Some('0') => unescaped.push('\0'),
+            Some('\\') => unescaped.push('\\'),
+            Some(other) => {
+                unescaped.push('\\');
+                unescaped.push(other);
+            }
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+/// Parses a `FIELD:FRACTION` spec for sampling by hash, e.g. `2:0.25`.
+fn parse_hash_sample(arg: &str) -> CliResult<(usize, f64)> {
+    let illegal = || format!("kat: illegal hash sample: '{}'", arg);
+    let (field, fraction) = arg.split_once(':').ok_or_else(illegal)?;
+    let field: usize = field.parse().map_err(|_| illegal())?;
+    let fraction: f64 = fraction.parse().map_err(|_| illegal())?;
+    if field == 0 {
+        return Err(KatError::ZeroInList);
+    }
+    if !(0.0..=1.0).contains(&fraction) {
+        return Err(illegal().into());
+    }
+    Ok((field - 1, fraction))
+}
+
+/// Parses a comma separated list of sort keys, each being a field number optionally followed by
+/// `n` for a numeric rather than lexical comparison and/or `r` to reverse the order.
+fn parse_sort_keys(arg: &str) -> CliResult<Vec<SortKey>> {
+    let mut keys = vec![];
+    for part in arg.split(',') {
+        let illegal = || format!("kat: illegal sort key: '{}'", part);
+        let digits = part.trim_end_matches(['n', 'r']);
+        let flags = &part[digits.len()..];
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) || flags.len() > 2 {
+            return Err(illegal().into());
+        }
+        let numeric = flags.contains('n');
+        let reverse = flags.contains('r');
+        if flags.len() != usize::from(numeric) + usize::from(reverse) {
+            return Err(illegal().into());
+        }
+        let field: usize = digits.parse().map_err(|_| illegal())?;
+        if field == 0 {
+            return Err(KatError::ZeroInList);
+        }
+        keys.push(SortKey {
+            field: field - 1,
+            numeric,
+            reverse,
+        });
+    }
+    Ok(keys)
+}
+
+/// Reads a list given in a file, where the items can be split up by whitespace as well as commas,
+/// into the form it would take on the command line.
+fn read_list(path: &str) -> CliResult<String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("kat: {}: {}", path, e))?;
+    let items = text
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|item| !item.is_empty())
+        .collect::<Vec<_>>();
+    Ok(items.join(","))
+}
+
+fn parse_selector(args: &Args) -> CliResult<Selector> {
+    // A selector given on the command line replaces the one in the config file rather than
+    // conflicting with it
+    let ids = ["fields", "characters", "bytes"];
+    let cli_only = ids
+        .iter()
+        .any(|id| args.on_cli(id) || args.on_cli(&format!("{}_file", id)));
+    let get = |id: &str| {
+        if cli_only {
+            args.matches.get_one::<String>(id).cloned()
+        } else {
+            args.string(id)
+        }
+    };
+    // A list too long for the command line can be read from a file instead
+    let extract = |id: &str| -> CliResult<Option<String>> {
+        match (get(id), get(&format!("{}_file", id))) {
+            (Some(list), _) => Ok(Some(list)),
+            (None, Some(path)) => read_list(&path).map(Some),
+            (None, None) => Ok(None),
+        }
+    };
+    // The order the positions were listed in only matters to these
+    let listed = args.flag("keep_order") || args.flag("group_sum");
+    let parse = if listed {
+        parse_listed_positions
+    } else {
+        parse_positions
+    };
+    let zero_based = args.flag("zero_based");
+    let tail_anchored = args.flag("tail_anchored");
+    let extract_positions = |id| {
+        extract(id)?
+            .map(|s| match tail_anchored {
+                true => parse_anchored_positions(s.as_str(), zero_based, listed),
+                false => parse(s.as_str(), zero_based),
+            })
+            .transpose()
+    };
+
+    if args.flag("from_ndjson") {
+        // NDJSON fields are picked by key rather than by position
+        return match extract("fields")? {
+            Some(keys) => Ok(Selector::Keys(keys.split(',').map(String::from).collect())),
+            None => Err("kat: --from-ndjson needs the keys to select given with --fields".into()),
+        };
+    }
+
+    if let Some(pattern) = args.string("header_regex") {
+        let regex =
+            Regex::new(&pattern).map_err(|e| format!("kat: invalid header regex: {}", e))?;
+        return Ok(Selector::NamesMatching(regex));
+    }
+
+    if args.flag("header_names") {
+        return match extract("fields")? {
+            Some(names) => Ok(Selector::Names(
+                names.split(',').map(String::from).collect(),
+            )),
+            None => Err("kat: --header-names needs the names to select given with --fields".into()),
+        };
+    }
+
+    let fields = extract_positions("fields")?;
+    let chars = extract_positions("characters")?;
+    let bytes = extract_positions("bytes")?;
+
+    if let Some(positions) = fields {
+        Ok(Selector::Fields(positions))
+    } else if let Some(positions) = chars {
+        Ok(Selector::Chars(positions))
+    } else if let Some(positions) = bytes {
+        Ok(Selector::Bytes(positions))
+    } else {
+        Err("Must have --fields, --bytes, or --chars".into())
+    }
+}
+
+#[cfg(test)]
+mod lib_tests {
+    use crate::{
+        cut_line, is_broken_pipe, open, open_retrying, parse_anchored_positions, parse_config,
+        parse_hash_sample, parse_listed_positions, parse_positions, parse_sort_keys, pretty_error,
+        process_reader, run, select_fields, select_lines, show_ranges, total_size, CliResult,
+        ConfigBuilder, Follow, Indices, KatError, Progress, Selection, Selector, SortKey, FROM_END,
+        OPEN_END,
+    };
+    use csv::StringRecord;
+    use std::fs::{self, File, OpenOptions};
+    use std::io::{self, BufReader, Write};
+    use std::sync::atomic::Ordering as AtomicOrdering;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use std::{assert_eq, vec};
+
+    #[test]
+    fn test_no_args() {
+        let cfg = parse_config(to_owned_arg_list(vec!["kat", "-b", "1"]));
+
+        assert!(cfg.is_ok());
+        let cfg = cfg.unwrap();
+        assert_eq!(cfg.delimiter, b'\t');
+        assert_eq!(cfg.files, vec!["-"]);
+    }
+
+    #[test]
+    fn test_custom_delim() {
+        let args = to_owned_arg_list(vec!["kat", "-d", "x", "-f", "1", "a.txt", "b.txt"]);
+        let cfg = parse_config(args);
+
+        assert!(cfg.is_ok());
+        let cfg = cfg.unwrap();
+        assert_eq!(cfg.delimiter, b'x');
+        assert_eq!(cfg.output_delimiter, b'x');
+        assert_eq!(cfg.files, vec!["a.txt", "b.txt"]);
+    }
+
+    #[test]
+    fn test_output_delim() {
+        let args = to_owned_arg_list(vec!["kat", "-f", "1", "--output-delimiter", ","]);
+        let cfg = parse_config(args).unwrap();
+        assert_eq!(cfg.delimiter, b'\t');
+        assert_eq!(cfg.output_delimiter, b',');
+
+        // Fields are joined up by hand with anything longer than a byte
+        let args = to_owned_arg_list(vec!["kat", "-f", "1", "--output-delimiter", "::"]);
+        let cfg = parse_config(args).unwrap();
+        assert_eq!(cfg.output_delimiter, b'\t');
+        assert_eq!(cfg.joiner.as_deref(), Some("::"));
+
+        let args = to_owned_arg_list(vec!["kat", "-f", "1", "--output-delimiter", ""]);
+        let cfg = parse_config(args);
+        assert!(cfg.is_err());
+        assert_eq!(cfg.unwrap_err().to_string(), "kat: bad output delimiter");
+    }
+
+    #[test]
+    fn test_bad_delim() {
+        let args = to_owned_arg_list(vec!["kat", "-d", "xxx", "-f", "1"]);
+        let cfg = parse_config(args);
+
+        assert!(cfg.is_err());
+        assert_eq!(cfg.unwrap_err().to_string(), "kat: bad delimiter");
+    }
+
+    #[test]
+    fn test_escaped_delim() {
+        let args = to_owned_arg_list(vec!["kat", "-d", r"\t", "-f", "1"]);
+        assert_eq!(parse_config(args).unwrap().delimiter, b'\t');
+
+        let args = to_owned_arg_list(vec!["kat", "-d", r"\\", "-f", "1"]);
+        assert_eq!(parse_config(args).unwrap().delimiter, b'\\');
+
+        let args = to_owned_arg_list(vec!["kat", "-d", r"\0", "-f", "1"]);
+        assert_eq!(parse_config(args).unwrap().delimiter, b'\0');
+
+        let args = to_owned_arg_list(vec![
+            "kat",
+            "-d",
+            ",",
+            "--output-delimiter",
+            r"\t",
+            "-f",
+            "1",
+        ]);
+        assert_eq!(parse_config(args).unwrap().output_delimiter, b'\t');
+
+        // Still two bytes once unescaped
+        let args = to_owned_arg_list(vec!["kat", "-d", r"\x", "-f", "1"]);
+        let cfg = parse_config(args);
+        assert!(cfg.is_err());
+        assert_eq!(cfg.unwrap_err().to_string(), "kat: bad delimiter");
+    }
+
+    #[test]
+    fn test_run_with_missing_file() {
+        let missing = std::env::temp_dir().join("kat_test_no_such_file.tsv");
+        let missing = missing.to_str().unwrap();
+        let args = vec!["kat", "-f", "1", missing, "tests/inputs/movies1.tsv"];
+        let res = run(parse_config(to_owned_arg_list(args)).unwrap());
+        assert!(res.is_err());
+        assert_eq!(
+            res.unwrap_err().to_string(),
+            "kat: 1 of 2 files could not be read"
+        );
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn test_open_gzipped() {
+        use crate::open;
+        use flate2::{write::GzEncoder, Compression};
+
+        let path = std::env::temp_dir().join("kat_test_open_gzipped.tsv.gz");
+        let mut encoder = GzEncoder::new(File::create(&path).unwrap(), Compression::default());
+        encoder.write_all(b"a\tb\nc\td\n").unwrap();
+        encoder.finish().unwrap();
+        let path = path.to_str().unwrap();
+
+        let cfg = parse_config(to_owned_arg_list(vec!["kat", "-f", "2", path])).unwrap();
+        let mut out = Vec::new();
+        process_reader(open(path, &cfg).unwrap(), &cfg, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "b\nd\n");
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn test_decompressed() {
+        use crate::{decompressed, Compression};
+        use flate2::write::GzEncoder;
+        use std::io::{Cursor, Read};
+
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(b"a\tb\nc\td\n").unwrap();
+        let gzipped = encoder.finish().unwrap();
+
+        let cfg = parse_config(to_owned_arg_list(vec![
+            "kat",
+            "-f",
+            "2",
+            "--decompress",
+            "gz",
+        ]))
+        .unwrap();
+        let mut out = Vec::new();
+        let reader = decompressed(Cursor::new(gzipped.clone()), Some(Compression::Gzip)).unwrap();
+        process_reader(reader, &cfg, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "b\nd\n");
+
+        // Left as it is otherwise
+        let mut raw = Vec::new();
+        decompressed(Cursor::new(gzipped.clone()), None)
+            .unwrap()
+            .read_to_end(&mut raw)
+            .unwrap();
+        assert_eq!(raw, gzipped);
+    }
+
+    #[cfg(all(feature = "zstd", feature = "bzip2"))]
+    #[test]
+    fn test_decompressed_zstd_bzip2() {
+        use crate::{decompressed, Compression};
+        use bzip2::write::BzEncoder;
+        use std::io::{Cursor, Read};
+
+        let zstd = zstd::encode_all(&b"a\tb\n"[..], 0).unwrap();
+        let mut encoder = BzEncoder::new(Vec::new(), bzip2::Compression::default());
+        encoder.write_all(b"a\tb\n").unwrap();
+        let bzip2 = encoder.finish().unwrap();
+        for (compressed, compression) in [(zstd, Compression::Zstd), (bzip2, Compression::Bzip2)] {
+            let mut text = String::new();
+            decompressed(Cursor::new(compressed), Some(compression))
+                .unwrap()
+                .read_to_string(&mut text)
+                .unwrap();
+            assert_eq!(text, "a\tb\n");
+        }
+        assert_eq!(Compression::of_file("x.tsv.zst"), Some(Compression::Zstd));
+        assert_eq!(Compression::of_file("x.tsv.bz2"), Some(Compression::Bzip2));
+        assert_eq!(Compression::of_file("x.tsv"), None);
+    }
+
+    #[test]
+    fn test_follow() {
+        let path = std::env::temp_dir().join("kat_test_follow.txt");
+        std::fs::write(&path, "abc\ndef\n").unwrap();
+        let cfg = parse_config(to_owned_arg_list(vec!["kat", "-c", "2"])).unwrap();
+
+        let appender = {
+            let path = path.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(50));
+                let mut file = OpenOptions::new().append(true).open(path).unwrap();
+                file.write_all(b"ghi\n").unwrap();
+            })
+        };
+
+        let reader = Follow {
+            inner: File::open(&path).unwrap(),
+            poll: Duration::from_millis(10),
+            idle_limit: Some(50),
+        };
+        let mut out = Vec::new();
+        process_reader(BufReader::new(reader), &cfg, &mut out).unwrap();
+        appender.join().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(String::from_utf8(out).unwrap(), "b\ne\nh\n");
+    }
+
+    #[test]
+    fn test_follow_needs_one_file() {
+        let args = to_owned_arg_list(vec!["kat", "-f", "1", "--follow"]);
+        assert!(parse_config(args).is_err());
+        let args = to_owned_arg_list(vec!["kat", "-f", "1", "--follow", "a.txt", "b.txt"]);
+        assert!(parse_config(args).is_err());
+    }
+
+    #[test]
+    fn test_open_retrying_other_errors() {
+        let mut calls = 0;
+        let result = open_retrying(3, || -> io::Result<()> {
+            calls += 1;
+            Err(io::ErrorKind::NotFound.into())
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_open_retrying_sharing_violation() {
+        // Fails with ERROR_SHARING_VIOLATION until the third attempt
+        let calls = std::cell::Cell::new(0);
+        let locked_twice = || -> io::Result<u32> {
+            calls.set(calls.get() + 1);
+            if calls.get() < 3 {
+                Err(io::Error::from_raw_os_error(32))
+            } else {
+                Ok(calls.get())
+            }
+        };
+        assert_eq!(open_retrying(2, locked_twice).unwrap(), 3);
+
+        calls.set(0);
+        assert!(open_retrying(1, locked_twice).is_err());
+        assert_eq!(calls.get(), 2);
+    }
+
+    #[test]
+    fn test_cut_line() {
+        let positions = parse_positions("1,3", false).unwrap();
+        assert_eq!(
+            cut_line("a\tbb\tc", &Selector::Bytes(positions.clone()), b'\t'),
+            "ab"
+        );
+        assert_eq!(
+            cut_line("héllo", &Selector::Chars(positions.clone()), b'\t'),
+            "hl"
+        );
+        assert_eq!(
+            cut_line("a\tbb\tc", &Selector::Fields(positions.clone()), b'\t'),
+            "a\tc"
+        );
+        assert_eq!(
+            cut_line("a,\"b,b\",c", &Selector::Fields(positions), b','),
+            "a,c"
+        );
+
+        // Fields which need quoting are quoted on the way out
+        let positions = parse_positions("2-", false).unwrap();
+        assert_eq!(
+            cut_line("a,\"b,b\",c", &Selector::Fields(positions), b','),
+            "\"b,b\",c"
+        );
+
+        // Positions past the end of the line are left out
+        let positions = parse_positions("2,9", false).unwrap();
+        assert_eq!(cut_line("abc", &Selector::Chars(positions), b'\t'), "b");
+
+        // As is a line without the delimiter, quotes and all
+        let positions = parse_positions("2", false).unwrap();
+        let fields = Selector::Fields(positions);
+        assert_eq!(cut_line("plainline", &fields, b'\t'), "plainline");
+        assert_eq!(cut_line("\"a\tb\"", &fields, b'\t'), "\"a\tb\"");
+
+        let names = Selector::Names(vec!["name".to_owned()]);
+        assert_eq!(cut_line("id,name", &names, b','), "name");
+        assert_eq!(cut_line("id,email", &names, b','), "");
+    }
+
+    #[cfg(feature = "ndjson")]
+    #[test]
+    fn test_cut_line_keys() {
+        let keys = Selector::Keys(vec!["name".to_owned(), "age".to_owned()]);
+        assert_eq!(cut_line(r#"{"age": 3, "name": "al"}"#, &keys, b','), "al,3");
+        assert_eq!(cut_line(r#"{"name": "al"}"#, &keys, b','), "al,");
+        assert_eq!(cut_line("not json", &keys, b','), "");
+    }
+
+    #[test]
+    fn test_select_lines() {
+        let cut = |args: Vec<&str>, input: &'static [u8]| {
+            let cfg = parse_config(to_owned_arg_list(args)).unwrap();
+            select_lines(input, &cfg).collect::<CliResult<Vec<_>>>()
+        };
+        let input = &b"a\tbb\tc\nd\te\tf\n"[..];
+        assert_eq!(
+            cut(vec!["kat", "-f", "1,3"], input).unwrap(),
+            ["a\tc", "d\tf"]
+        );
+        assert_eq!(
+            cut(vec!["kat", "-c", "2-"], input).unwrap(),
+            ["\tbb\tc", "\te\tf"]
+        );
+        assert_eq!(
+            cut(vec!["kat", "-b", "1", "-N"], input).unwrap(),
+            ["1\ta", "2\td"]
+        );
+        assert_eq!(
+            cut(vec!["kat", "-f", "2", "-N"], input).unwrap(),
+            ["1\tbb", "2\te"]
+        );
+
+        // Fields are quoted where needed but never end up with the line terminator
+        let input = &b"a,\"b,b\"\r\n"[..];
+        let args = vec!["kat", "-d", ",", "-f", "2"];
+        assert_eq!(cut(args, input).unwrap(), ["\"b,b\""]);
+
+        let input = &b"id,name\n1,al\n"[..];
+        let args = vec!["kat", "-d", ",", "--header-names", "-f", "name"];
+        assert_eq!(cut(args, input).unwrap(), ["name", "al"]);
+        let args = vec!["kat", "-d", ",", "--header-names", "-f", "nope"];
+        assert!(cut(args, input).is_err());
+
+        // Like cut, lines without a delimiter are written out as they are
+        let input = &b"a\tb\nplain \"line\"\n"[..];
+        assert_eq!(
+            cut(vec!["kat", "-f", "2"], input).unwrap(),
+            ["b", "plain \"line\""]
+        );
+        assert_eq!(cut(vec!["kat", "-f", "2", "-s"], input).unwrap(), ["b"]);
+
+        // A CR ending the line doesn't end up in the last field or char
+        let input = &b"a\tb\r\n"[..];
+        assert_eq!(cut(vec!["kat", "-f", "2"], input).unwrap(), ["b"]);
+        assert_eq!(cut(vec!["kat", "-c", "3-"], input).unwrap(), ["b"]);
+        assert_eq!(
+            cut(vec!["kat", "-c", "3-", "--keep-cr"], input).unwrap(),
+            ["b\r"]
+        );
+
+        // Nothing is read until the lines are asked for
+        let cfg = parse_config(to_owned_arg_list(vec!["kat", "-c", "1"])).unwrap();
+        let mut lines = select_lines(&b"ab\n\xff\ncd\n"[..], &cfg);
+        assert_eq!(lines.next().unwrap().unwrap(), "a");
+        assert!(lines.next().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_config_builder() {
+        let cfg = ConfigBuilder::new()
+            .delimiter(",")
+            .output_delimiter("\\t")
+            .fields("3,1")
+            .build()
+            .unwrap();
+        let lines = select_lines(&b"a,b,c\n\"d,e\",f,g\n"[..], &cfg);
+        assert_eq!(
+            lines.collect::<CliResult<Vec<_>>>().unwrap(),
+            ["a\tc", "d,e\tg"]
+        );
+
+        // The same checks apply as on the command line
+        let res = ConfigBuilder::new().fields("1").delimiter(",,").build();
+        assert_eq!(res.unwrap_err().to_string(), "kat: bad delimiter");
+        let res = ConfigBuilder::new().fields("0").build();
+        assert!(res.is_err());
+        let res = ConfigBuilder::new().files(["-"]).build();
+        assert_eq!(
+            res.unwrap_err().to_string(),
+            "Must have --fields, --bytes, or --chars"
+        );
+    }
 
-pub fn run(config: Config) -> CliResult<()> {
-    for file in &config.files {
-        match open(file.as_str()) {
-            Err(e) => eprintln!("{}: {}", file, e),
-            Ok(reader) => process_reader(reader, &config)?,
+    #[test]
+    fn test_indices() {
+        // Every index lands on the same side of the cut as it would had we checked the positions
+        // one by one
+        for list in ["1", "5-7,1-3,2-4,9-", "3,1,2", "2-3,1-4", "4-,2-3", "-2,2-"] {
+            let positions = parse_positions(list, false).unwrap();
+            let indices = Indices::new(&positions);
+            let expected = (0..12)
+                .filter(|idx| positions.iter().any(|r| r.contains(idx)))
+                .collect::<Vec<_>>();
+            assert_eq!(indices.of(12).collect::<Vec<_>>(), expected, "{}", list);
+            for idx in 0..12 {
+                assert_eq!(indices.contains(&idx), expected.contains(&idx), "{}", list);
+            }
         }
+
+        let indices = Indices::new(&parse_positions("5,2-3,7-", false).unwrap());
+        assert_eq!(indices.up_to(9), vec![1, 2, 4, 6, 7, 8]);
+        assert_eq!(indices.up_to(3), vec![1, 2, 4]);
     }
-    Ok(())
-}
 
-fn process_reader(reader: impl BufRead, config: &Config) -> CliResult<()> {
-    let unique_indices = |positions: &Positions| {
-        // The clone below is needed because we can't collect a range we don't own and iter
-        // gives us back the reference to a range. We could also make this work with into_iter
-        // on positions but then our iter owns the data which is something we don't want!
-        positions
-            .iter()
-            .flat_map(|r| r.clone().collect::<Vec<usize>>())
-            .collect::<HashSet<usize>>()
-    };
+    #[test]
+    fn test_show_ranges() {
+        let fields = Selector::Fields(parse_positions("1,7,3-5", false).unwrap());
+        assert_eq!(show_ranges(&fields), "fields: 0..1, 2..5, 6..7");
+        let chars = Selector::Chars(parse_positions("-2,4-", false).unwrap());
+        assert_eq!(show_ranges(&chars), "characters: 0..2, 3..");
+        let bytes = Selector::Bytes(parse_anchored_positions("2,-1", false, false).unwrap());
+        assert_eq!(show_ranges(&bytes), "bytes: 1..2, -1");
+        let keys = Selector::Keys(vec!["a".to_string(), "b".to_string()]);
+        assert_eq!(show_ranges(&keys), "keys: a, b");
+    }
 
-    match &config.selector {
-        Selector::Bytes(positions) => {
-            let all_pos = unique_indices(positions);
-            for result in reader.lines() {
-                let line = result?;
-                let mut byte_buf = Vec::new();
-                for (idx, b) in line.as_bytes().iter().enumerate() {
-                    if all_pos.contains(&idx) {
-                        byte_buf.push(*b);
-                    }
-                }
-                // Since it's possible for us to pick off random bytes from a multi-byte seq
-                // in a UTF-8 file, it's important to create a lossy string to avoid an error
-                // at runtime.
-                println!("{}", String::from_utf8_lossy(byte_buf.as_slice()));
-            }
-        }
-        Selector::Chars(positions) => {
-            let all_pos = unique_indices(positions);
-            for result in reader.lines() {
-                let line = result?;
-                let mut char_buf = Vec::new();
-                for (idx, c) in line.chars().enumerate() {
-                    if all_pos.contains(&idx) {
-                        char_buf.push(c);
-                    }
-                }
-                let mut line = String::new();
-                line.extend(char_buf);
-                println!("{}", line);
+    #[test]
+    fn test_progress() {
+        // Somewhere for the progress to be shown which can be looked at afterwards
+        #[derive(Clone, Default)]
+        struct Shown(Arc<Mutex<Vec<u8>>>);
+        impl Write for Shown {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
             }
-        }
-        Selector::Fields(positions) => {
-            let all_pos = unique_indices(positions);
-            let mut csv_reader = csv::ReaderBuilder::new()
-                .delimiter(config.delimiter)
-                .has_headers(false)
-                .from_reader(reader);
-            let mut csv_writer = csv::WriterBuilder::new()
-                .delimiter(config.delimiter)
-                .from_writer(stdout());
-
-            let mut printer = |record: &mut &StringRecord| -> CliResult<()> {
-                for (idx, val) in record.iter().enumerate() {
-                    if all_pos.contains(&idx) {
-                        csv_writer.write_field(val)?;
-                    }
-                }
-                csv_writer.write_record(None::<&[u8]>)?;
+            fn flush(&mut self) -> io::Result<()> {
                 Ok(())
-            };
-
-            for result in csv_reader.records() {
-                let record = result?;
-                printer(&mut &record)?;
             }
         }
-    }
-    Ok(())
-}
 
-fn open(file: &str) -> CliResult<Box<dyn BufRead>> {
-    match file {
-        "-" => Ok(Box::new(BufReader::new(stdin()))),
-        _ => Ok(Box::new(BufReader::new(File::open(file)?))),
-    }
-}
-
-pub fn parse_config(cmd_args: Vec<String>) -> CliResult<Config> {
-    let matches = App::new("kat")
-        .version("0.1.0")
-        .author("sanjayts")
-        .arg(
-            Arg::new("delimiter")
-                .value_name("DELIM")
-                .short('d')
-                .long("delimiter")
-                .help("Use DELIM instead of TAB for delimiter")
-                .default_value("\t")
-                .takes_value(true)
-                .multiple_values(false),
-        )
-        .arg(
-            Arg::new("fields")
-                .value_name("LIST")
-                .short('f')
-                .long("fields")
-                .help("select only these fields")
-                .takes_value(true)
-                .conflicts_with_all(&["bytes", "characters"]),
-        )
-        .arg(
-            Arg::new("characters")
-                .value_name("LIST")
-                .short('c')
-                .long("characters")
-                .help("select only these characters")
-                .takes_value(true)
-                .conflicts_with_all(&["bytes", "fields", "delimiter"]),
-        )
-        .arg(
-            Arg::new("bytes")
-                .value_name("LIST")
-                .short('b')
-                .long("bytes")
-                .help("select only these bytes")
-                .multiple_values(false)
-                .conflicts_with_all(&["fields", "characters", "delimiter"]),
-        )
-        .arg(
-            Arg::new("files")
-                .value_name("FILE")
-                .takes_value(true)
-                .multiple_values(true)
-                .default_value("-"),
-        )
-        .get_matches_from(cmd_args);
+        let path = std::env::temp_dir().join("kat_test_progress.tsv");
+        fs::write(&path, "a\tb\n".repeat(100_000)).unwrap();
+        let path = path.to_str().unwrap().to_owned();
+        let mut config = parse_config(to_owned_arg_list(vec!["kat", "-f", "2", &path])).unwrap();
+        let shown = Shown::default();
+        let total_bytes = total_size(&config.files);
+        assert_eq!(total_bytes, Some(400_000));
+        let progress = Arc::new(Progress::new(total_bytes, Box::new(shown.clone())));
+        config.progress = Some(progress.clone());
 
-    let files = matches
-        .get_many::<String>("files")
-        .unwrap()
-        .map(|s| s.to_owned())
-        .collect();
+        let mut out = Vec::new();
+        process_reader(open(&path, &config).unwrap(), &config, &mut out).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(out.len(), 200_000);
+        assert_eq!(progress.lines.load(AtomicOrdering::Relaxed), 100_000);
+        assert_eq!(progress.bytes.load(AtomicOrdering::Relaxed), 400_000);
 
-    let delimiter = matches.get_one::<String>("delimiter").unwrap().to_owned();
-    if delimiter.len() != 1 {
-        return Err("kat: bad delimiter".into());
+        progress.finish();
+        let shown = String::from_utf8(shown.0.lock().unwrap().clone()).unwrap();
+        assert!(shown.ends_with("\rkat: 100000 lines, 400000 of 400000 bytes\n"));
+        assert_eq!(total_size(&["-".to_owned()]), None);
     }
 
-    let selector = parse_selector(&matches)?;
-
-    let config = Config {
-        files,
-        delimiter: delimiter.bytes().next().unwrap(),
-        selector,
-    };
-    Ok(config)
-}
+    #[test]
+    fn test_is_broken_pipe() {
+        let broken = || io::Error::from(io::ErrorKind::BrokenPipe);
+        assert!(is_broken_pipe(&broken().into()));
+        // As it comes back from the csv writer
+        assert!(is_broken_pipe(&csv::Error::from(broken()).into()));
 
-fn parse_positions(arg: &str) -> CliResult<Positions> {
-    let func = |v: &str| format!("kat: illegal list value: '{}'", v);
-    if arg.is_empty() || arg.starts_with(',') || arg.ends_with(',') {
-        return Err(func(arg).into());
+        assert!(!is_broken_pipe(
+            &io::Error::from(io::ErrorKind::NotFound).into()
+        ));
+        assert!(!is_broken_pipe(&"kat: Broken pipe".into()));
     }
 
-    let parse_num = |s: &str| -> CliResult<usize> {
-        if s.starts_with('-') || s.starts_with('+') || s.ends_with('-') || s.ends_with('+') {
-            return Err(func(s).into());
-        }
-        // Without the explicit cast below, map_err can't infer that we want a dyn Error
-        let num = s.parse().map_err::<Box<dyn Error>, _>(|_| func(s).into())?;
-        if num == 0 {
-            Err("kat: list values may not include zero".into())
-        } else {
-            Ok(num)
+    #[test]
+    fn test_cut_bytes() {
+        // Reading into the one buffer has to write out just what cutting line by line would
+        let input = b"abc\r\n\xe9t\xc3\xa9\n\n\xffxyz\nno newline";
+        for args in [
+            vec!["kat", "-b", "1,3-"],
+            vec!["kat", "-b", "2", "-n"],
+            vec!["kat", "-b", "1-2", "-N"],
+            vec!["kat", "-b", "2,1", "--keep-order", "--keep-cr"],
+            vec!["kat", "-b", "-2", "--output-delimiter", ":", "-z"],
+            vec!["kat", "-b", "1,3", "--hex", "-m", "3"],
+            vec!["kat", "-b", "2", "--min-length", "3", "-m", "2", "-N"],
+        ] {
+            let config = parse_config(to_owned_arg_list(args.clone())).unwrap();
+            let mut expected = Vec::new();
+            for line in select_lines(&input[..], &config) {
+                expected.extend_from_slice(line.unwrap().as_bytes());
+                expected.extend_from_slice(config.terminator.as_bytes());
+            }
+            let mut out = Vec::new();
+            process_reader(&input[..], &config, &mut out).unwrap();
+            assert_eq!(out, expected, "{:?}", args);
         }
-    };
+    }
 
-    let mut positions = vec![];
-    for part in arg.split(',') {
-        if part.starts_with('-') || part.ends_with('-') {
-            return Err(func(part).into());
+    #[test]
+    fn test_selector_from_str() {
+        match "b:1,3".parse::<Selector>().unwrap() {
+            Selector::Bytes(positions) => assert_eq!(positions, vec![0..1, 2..3]),
+            selector => panic!("{:?}", selector),
         }
-
-        let inner_parts = part.split('-').collect::<Vec<_>>();
-        if inner_parts.len() == 1 {
-            let n: usize = parse_num(inner_parts[0])?;
-            positions.push((n - 1)..n);
-        } else if inner_parts.len() == 2 {
-            let start: usize = parse_num(inner_parts[0])?;
-            let end: usize = parse_num(inner_parts[1])?;
-            if end <= start {
-                let msg = format!(
-                    "First number in range ({}) must be lower than second number ({})",
-                    start, end
-                );
-                return Err(msg.into());
+        match "c:1-5".parse::<Selector>().unwrap() {
+            Selector::Chars(positions) => {
+                assert_eq!(positions, parse_positions("1-5", false).unwrap())
             }
-            positions.push((start - 1)..end);
-        } else {
-            return Err(func(part).into());
+            selector => panic!("{:?}", selector),
+        }
+        match "f:2-".parse::<Selector>().unwrap() {
+            Selector::Fields(positions) => {
+                assert_eq!(positions, parse_positions("2-", false).unwrap())
+            }
+            selector => panic!("{:?}", selector),
         }
-    }
-    Ok(positions)
-}
-
-fn parse_selector(matches: &ArgMatches) -> CliResult<Selector> {
-    let extract_positions = |id| {
-        matches
-            .get_one::<String>(id)
-            .map(|s| parse_positions(s.as_str()))
-            .transpose()
-    };
-
-    let fields = extract_positions("fields")?;
-    let chars = extract_positions("characters")?;
-    let bytes = extract_positions("bytes")?;
 
-    if let Some(positions) = fields {
-        Ok(Selector::Fields(positions))
-    } else if let Some(positions) = chars {
-        Ok(Selector::Chars(positions))
-    } else if let Some(positions) = bytes {
-        Ok(Selector::Bytes(positions))
-    } else {
-        Err("Must have --fields, --bytes, or --chars".into())
+        let res = "x:1".parse::<Selector>();
+        assert_eq!(
+            res.unwrap_err().to_string(),
+            "kat: bad selector: 'x:1' (it has to start with b:, c: or f:)"
+        );
+        assert!("1,3".parse::<Selector>().is_err());
+        let res = "f:0".parse::<Selector>();
+        assert_eq!(
+            res.unwrap_err().to_string(),
+            "kat: list values may not include zero"
+        );
     }
-}
 
-#[cfg(test)]
-mod lib_tests {
-    use crate::{parse_config, parse_positions};
-    use std::{assert_eq, vec};
+    #[test]
+    fn test_select_fields() {
+        let record = StringRecord::from(vec!["a", "b", "c", "d"]);
+        let select = |list| select_fields(&record, &parse_positions(list, false).unwrap());
+        assert_eq!(select("1,3"), vec!["a", "c"]);
+        assert_eq!(select("3,1"), vec!["a", "c"]);
+        assert_eq!(select("2-"), vec!["b", "c", "d"]);
+        assert_eq!(select("1-2,2-3"), vec!["a", "b", "c"]);
+        // Positions listed over and over again are still only picked once
+        let positions = parse_listed_positions("4,2,4", false).unwrap();
+        assert_eq!(select_fields(&record, &positions), vec!["b", "d"]);
+        // Past the end of the record there's nothing to select
+        assert_eq!(select("3,9"), vec!["c"]);
+        assert!(select("5-").is_empty());
+        assert!(
+            select_fields(&StringRecord::new(), &parse_positions("1", false).unwrap()).is_empty()
+        );
+        // Positions counting back from the end are pinned down by the record's length
+        let positions = parse_anchored_positions("-1,1", false, false).unwrap();
+        assert_eq!(select_fields(&record, &positions), vec!["a", "d"]);
+    }
 
     #[test]
-    fn test_no_args() {
-        let cfg = parse_config(to_owned_arg_list(vec!["kat", "-b", "1"]));
+    fn test_anchored_positions() {
+        // Those counting back go after the rest unless the order they were listed in is kept
+        let positions = parse_anchored_positions("-1,2-3,1,-2", false, false).unwrap();
+        assert_eq!(
+            positions,
+            vec![0..3, FROM_END..FROM_END + 1, FROM_END + 1..FROM_END + 2]
+        );
+        let listed = parse_anchored_positions("-1,3,-2", false, true).unwrap();
+        assert_eq!(
+            listed,
+            vec![FROM_END..FROM_END + 1, 2..3, FROM_END + 1..FROM_END + 2]
+        );
+        assert!(parse_anchored_positions("-0", false, false).is_err());
+        assert!(parse_anchored_positions("--1", false, false).is_err());
+        assert!(parse_anchored_positions("-+1", false, false).is_err());
+        assert!(parse_anchored_positions("-1-", false, false).is_err());
+        assert!(parse_anchored_positions("-1,", false, false).is_err());
 
-        assert!(cfg.is_ok());
-        let cfg = cfg.unwrap();
-        assert_eq!(cfg.delimiter, b'\t');
-        assert_eq!(cfg.files, vec!["-"]);
+        // They're only pinned down once we know how long the line is, and picked out once each
+        // in ascending order along with the rest
+        let selection = Selection::new(&positions, false);
+        assert_eq!(
+            selection.at(5).of(5).collect::<Vec<_>>(),
+            vec![0, 1, 2, 3, 4]
+        );
+        assert_eq!(selection.at(2).of(2).collect::<Vec<_>>(), vec![0, 1]);
+        let selection = Selection::new(
+            &parse_anchored_positions("-1", false, false).unwrap(),
+            false,
+        );
+        assert_eq!(selection.at(4).of(4).collect::<Vec<_>>(), vec![3]);
+        assert_eq!(selection.at(1).of(1).collect::<Vec<_>>(), vec![0]);
+        assert_eq!(selection.at(0).of(0).count(), 0);
+
+        // Or in the order they were listed, repeats and all
+        let selection = Selection::new(&listed, true);
+        assert_eq!(selection.at(5).of(5).collect::<Vec<_>>(), vec![4, 2, 3]);
+        assert_eq!(selection.at(1).of(1).collect::<Vec<_>>(), vec![0]);
     }
 
     #[test]
-    fn test_custom_delim() {
-        let args = to_owned_arg_list(vec!["kat", "-d", "x", "-f", "1", "a.txt", "b.txt"]);
-        let cfg = parse_config(args);
+    fn test_pretty_error() {
+        assert_eq!(
+            pretty_error("invalid UTF-8", 12, "Zoë,\u{fffd}x".as_bytes(), 5),
+            "kat: invalid UTF-8 on line 12, column 5\n   |\n12 | Zoë,\u{fffd}x\n   |     ^"
+        );
+    }
 
-        assert!(cfg.is_ok());
-        let cfg = cfg.unwrap();
-        assert_eq!(cfg.delimiter, b'x');
-        assert_eq!(cfg.files, vec!["a.txt", "b.txt"]);
+    #[test]
+    fn test_parse_hash_sample() {
+        assert_eq!(parse_hash_sample("2:0.25").unwrap(), (1, 0.25));
+        assert_eq!(parse_hash_sample("1:1").unwrap(), (0, 1.0));
+        assert!(parse_hash_sample("2").is_err());
+        assert!(parse_hash_sample("a:0.5").is_err());
+        assert!(parse_hash_sample("2:1.5").is_err());
+        assert!(parse_hash_sample("2:-0.1").is_err());
+        assert_eq!(
+            parse_hash_sample("0:0.5").unwrap_err().to_string(),
+            "kat: list values may not include zero"
+        );
     }
 
     #[test]
-    fn test_bad_delim() {
-        let args = to_owned_arg_list(vec!["kat", "-d", "xxx", "-f", "1"]);
-        let cfg = parse_config(args);
+    fn test_parse_sort_keys() {
+        let keys = parse_sort_keys("2n,1").unwrap();
+        assert_eq!(
+            keys,
+            vec![
+                SortKey {
+                    field: 1,
+                    numeric: true,
+                    reverse: false
+                },
+                SortKey {
+                    field: 0,
+                    numeric: false,
+                    reverse: false
+                },
+            ]
+        );
 
-        assert!(cfg.is_err());
-        assert_eq!(cfg.unwrap_err().to_string(), "kat: bad delimiter");
+        let keys = parse_sort_keys("3rn").unwrap();
+        assert_eq!(
+            keys,
+            vec![SortKey {
+                field: 2,
+                numeric: true,
+                reverse: true
+            }]
+        );
+
+        assert!(parse_sort_keys("").is_err());
+        assert!(parse_sort_keys("n").is_err());
+        assert!(parse_sort_keys("2nn").is_err());
+        assert!(parse_sort_keys("2x").is_err());
+        assert!(parse_sort_keys("1,").is_err());
+        assert_eq!(
+            parse_sort_keys("0").unwrap_err().to_string(),
+            "kat: list values may not include zero"
+        );
     }
 
     #[test]
     fn test_parse_positions() {
         let arg = "1";
-        let positions = parse_positions(arg);
+        let positions = parse_positions(arg, false);
         assert!(positions.is_ok());
         assert_eq!(positions.unwrap(), vec![0..1]);
 
         let arg = "1-3";
-        let positions = parse_positions(arg);
+        let positions = parse_positions(arg, false);
         assert!(positions.is_ok());
         assert_eq!(positions.unwrap(), vec![0..3]);
 
         let arg = "1-3,8-10";
-        let positions = parse_positions(arg);
+        let positions = parse_positions(arg, false);
         assert!(positions.is_ok());
         assert_eq!(positions.unwrap(), vec![0..3, 7..10]);
 
         // The empty string is an error
-        assert!(parse_positions("").is_err());
+        assert!(parse_positions("", false).is_err());
 
         // Zero is an error
-        let res = parse_positions("0");
+        let res = parse_positions("0", false);
         assert!(res.is_err());
         assert_eq!(
             res.unwrap_err().to_string(),
             "kat: list values may not include zero"
         );
 
-        let res = parse_positions("0-1");
+        let res = parse_positions("0-1", false);
         assert!(res.is_err());
         assert_eq!(
             res.unwrap_err().to_string(),
@@ -326,21 +5604,21 @@ mod lib_tests {
         );
 
         // A leading "+" is an error
-        let res = parse_positions("+1");
+        let res = parse_positions("+1", false);
         assert!(res.is_err());
         assert_eq!(
             res.unwrap_err().to_string(),
             "kat: illegal list value: '+1'",
         );
 
-        let res = parse_positions("+1-2");
+        let res = parse_positions("+1-2", false);
         assert!(res.is_err());
         assert_eq!(
             res.unwrap_err().to_string(),
             "kat: illegal list value: '+1'",
         );
 
-        let res = parse_positions("+2");
+        let res = parse_positions("+2", false);
         assert!(res.is_err());
         assert_eq!(
             res.unwrap_err().to_string(),
@@ -348,50 +5626,50 @@ mod lib_tests {
         );
 
         // Any non-number is an error
-        let res = parse_positions("a");
+        let res = parse_positions("a", false);
         assert!(res.is_err());
         assert_eq!(res.unwrap_err().to_string(), "kat: illegal list value: 'a'",);
 
-        let res = parse_positions("1,a");
+        let res = parse_positions("1,a", false);
         assert!(res.is_err());
         assert_eq!(res.unwrap_err().to_string(), "kat: illegal list value: 'a'",);
 
-        let res = parse_positions("1-a");
+        let res = parse_positions("1-a", false);
         assert!(res.is_err());
         assert_eq!(res.unwrap_err().to_string(), "kat: illegal list value: 'a'",);
 
-        let res = parse_positions("a-1");
+        let res = parse_positions("a-1", false);
         assert!(res.is_err());
         assert_eq!(res.unwrap_err().to_string(), "kat: illegal list value: 'a'",);
 
         // Wonky ranges
-        let res = parse_positions("-");
+        let res = parse_positions("-", false);
         assert!(res.is_err());
 
-        let res = parse_positions(",");
+        let res = parse_positions(",", false);
         assert!(res.is_err());
 
-        let res = parse_positions("1,");
+        let res = parse_positions("1,", false);
         assert!(res.is_err());
 
-        let res = parse_positions("1-");
+        let res = parse_positions("--1", false);
         assert!(res.is_err());
 
-        let res = parse_positions("1-1-1");
+        let res = parse_positions("1-1-1", false);
         assert!(res.is_err());
 
-        let res = parse_positions("1-1-a");
+        let res = parse_positions("1-1-a", false);
         assert!(res.is_err());
 
         // First number must be less than second
-        let res = parse_positions("1-1");
+        let res = parse_positions("1-1", false);
         assert!(res.is_err());
         assert_eq!(
             res.unwrap_err().to_string(),
             "First number in range (1) must be lower than second number (1)"
         );
 
-        let res = parse_positions("2-1");
+        let res = parse_positions("2-1", false);
         assert!(res.is_err());
         assert_eq!(
             res.unwrap_err().to_string(),
@@ -399,37 +5677,252 @@ mod lib_tests {
         );
 
         // All the following are acceptable
-        let res = parse_positions("1");
+        let res = parse_positions("1", false);
         assert!(res.is_ok());
         assert_eq!(res.unwrap(), vec![0..1]);
 
-        let res = parse_positions("01");
+        let res = parse_positions("01", false);
         assert!(res.is_ok());
         assert_eq!(res.unwrap(), vec![0..1]);
 
-        let res = parse_positions("1,3");
+        let res = parse_positions("1,3", false);
         assert!(res.is_ok());
         assert_eq!(res.unwrap(), vec![0..1, 2..3]);
 
-        let res = parse_positions("001,0003");
+        let res = parse_positions("001,0003", false);
         assert!(res.is_ok());
         assert_eq!(res.unwrap(), vec![0..1, 2..3]);
 
-        let res = parse_positions("1-3");
+        let res = parse_positions("1-3", false);
         assert!(res.is_ok());
         assert_eq!(res.unwrap(), vec![0..3]);
 
-        let res = parse_positions("0001-03");
+        let res = parse_positions("0001-03", false);
         assert!(res.is_ok());
         assert_eq!(res.unwrap(), vec![0..3]);
 
-        let res = parse_positions("1,7,3-5");
+        let res = parse_positions("1,7,3-5", false);
         assert!(res.is_ok());
-        assert_eq!(res.unwrap(), vec![0..1, 6..7, 2..5]);
+        assert_eq!(res.unwrap(), vec![0..1, 2..5, 6..7]);
 
-        let res = parse_positions("15,19-20");
+        let res = parse_positions("15,19-20", false);
         assert!(res.is_ok());
         assert_eq!(res.unwrap(), vec![14..15, 18..20]);
+
+        // Ranges may be open on either end
+        let res = parse_positions("3-", false);
+        assert!(res.is_ok());
+        assert_eq!(res.unwrap(), vec![2..OPEN_END]);
+
+        let res = parse_positions("-5", false);
+        assert!(res.is_ok());
+        assert_eq!(res.unwrap(), vec![0..5]);
+
+        let res = parse_positions("1,4-", false);
+        assert!(res.is_ok());
+        assert_eq!(res.unwrap(), vec![0..1, 3..OPEN_END]);
+
+        // Overlapping and adjacent ranges are merged
+        let res = parse_positions("1-3,2-5", false);
+        assert!(res.is_ok());
+        assert_eq!(res.unwrap(), vec![0..5]);
+
+        let res = parse_positions("1-2,4-5", false);
+        assert!(res.is_ok());
+        assert_eq!(res.unwrap(), vec![0..2, 3..5]);
+
+        let res = parse_positions("4-5,1-3,2", false);
+        assert!(res.is_ok());
+        assert_eq!(res.unwrap(), vec![0..5]);
+
+        let res = parse_positions("5-,1,3-7", false);
+        assert!(res.is_ok());
+        assert_eq!(res.unwrap(), vec![0..1, 2..OPEN_END]);
+
+        // Unless the order they were listed in has to be kept
+        let res = parse_listed_positions("3,1-2,2", false);
+        assert!(res.is_ok());
+        assert_eq!(res.unwrap(), vec![2..3, 0..2, 1..2]);
+
+        let res = parse_positions("-0", false);
+        assert!(res.is_err());
+        assert_eq!(
+            res.unwrap_err().to_string(),
+            "kat: list values may not include zero"
+        );
+    }
+
+    #[test]
+    fn test_position_errors() {
+        assert!(matches!(
+            parse_positions("1,a", false),
+            Err(KatError::IllegalListValue(v)) if v == "a"
+        ));
+        assert!(matches!(
+            parse_positions("", false),
+            Err(KatError::IllegalListValue(v)) if v.is_empty()
+        ));
+        assert!(matches!(
+            parse_positions("0-2", false),
+            Err(KatError::ZeroInList)
+        ));
+        assert!(matches!(
+            parse_positions("3-2", false),
+            Err(KatError::EmptyRange { start: 3, end: 2 })
+        ));
+        // And still reported as they always were
+        let err = parse_positions("2-2", false).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "First number in range (2) must be lower than second number (2)"
+        );
+        assert!(matches!(
+            ConfigBuilder::new().fields("1").delimiter("").build(),
+            Err(KatError::Other(_))
+        ));
+        assert!(matches!(
+            ConfigBuilder::new().fields("1").delimiter(",,").build(),
+            Err(KatError::BadDelimiter)
+        ));
+    }
+
+    #[test]
+    fn test_parse_zero_based_positions() {
+        // The same list picks out the positions one along
+        assert_eq!(parse_positions("1", false).unwrap(), vec![0..1]);
+        assert_eq!(parse_positions("1", true).unwrap(), vec![1..2]);
+        assert_eq!(parse_positions("1-3", false).unwrap(), vec![0..3]);
+        assert_eq!(parse_positions("1-3", true).unwrap(), vec![1..4]);
+        assert_eq!(parse_positions("-2", false).unwrap(), vec![0..2]);
+        assert_eq!(parse_positions("-2", true).unwrap(), vec![0..3]);
+        assert_eq!(parse_positions("2-", false).unwrap(), vec![1..OPEN_END]);
+        assert_eq!(parse_positions("2-", true).unwrap(), vec![2..OPEN_END]);
+        assert_eq!(parse_positions("1,3-4", false).unwrap(), vec![0..1, 2..4]);
+        assert_eq!(parse_positions("1,3-4", true).unwrap(), vec![1..2, 3..5]);
+
+        // Zero is only allowed when counting from it
+        assert_eq!(parse_positions("0", true).unwrap(), vec![0..1]);
+        assert_eq!(parse_positions("0-1,3", true).unwrap(), vec![0..2, 3..4]);
+        assert!(parse_positions("0", false).is_err());
+        assert_eq!(
+            parse_positions("2-1", true).unwrap_err().to_string(),
+            "First number in range (2) must be lower than second number (1)"
+        );
+    }
+
+    #[test]
+    fn test_parse_all_positions() {
+        assert_eq!(parse_positions("*", false).unwrap(), vec![0..OPEN_END]);
+        assert_eq!(parse_positions("*", true).unwrap(), vec![0..OPEN_END]);
+        assert_eq!(
+            parse_anchored_positions("*", false, false).unwrap(),
+            vec![0..OPEN_END]
+        );
+        for list in ["*,1", "2,*", "1-2,*,4"] {
+            assert_eq!(
+                parse_positions(list, false).unwrap_err().to_string(),
+                "kat: '*' can't be listed along with other positions"
+            );
+            assert!(parse_anchored_positions(list, false, false).is_err());
+        }
+        assert!(parse_positions("*-2", false).is_err());
+        assert!(parse_positions("**", false).is_err());
+    }
+
+    #[test]
+    fn test_parse_stepped_positions() {
+        // Every step-th position from the start of the range up to (at most) its end
+        assert_eq!(
+            parse_listed_positions("1-9:2", false).unwrap(),
+            vec![0..1, 2..3, 4..5, 6..7, 8..9]
+        );
+        assert_eq!(
+            parse_listed_positions("2-10:3", false).unwrap(),
+            vec![1..2, 4..5, 7..8]
+        );
+        assert_eq!(
+            parse_positions("1-9:2,2", false).unwrap(),
+            vec![0..3, 4..5, 6..7, 8..9]
+        );
+        assert_eq!(
+            parse_listed_positions("0-4:2", true).unwrap(),
+            vec![0..1, 2..3, 4..5]
+        );
+        assert_eq!(parse_positions("1-3:1", false).unwrap(), vec![0..3]);
+        // A step of 1 isn't expanded at all, however far it goes, but others are only so far
+        assert_eq!(
+            parse_positions("1-1000000000000:1", false).unwrap(),
+            vec![0..1000000000000]
+        );
+        assert_eq!(
+            parse_positions("1-1000000000000:2", false).unwrap_err().to_string(),
+            "kat: too many positions in '1-1000000000000:2' (a stepped range can't pick out more than 1048576)"
+        );
+        assert_eq!(
+            parse_listed_positions("1-2097152:2", false).unwrap().len(),
+            1 << 20
+        );
+
+        assert_eq!(
+            parse_positions("1-9:0", false).unwrap_err().to_string(),
+            "kat: illegal step: '1-9:0'"
+        );
+        assert!(parse_positions("1-9:", false).is_err());
+        assert!(parse_positions("1-9:x", false).is_err());
+        assert!(parse_positions("1-9:-2", false).is_err());
+        // Open-ended ranges and single positions have nothing to step through
+        assert!(parse_positions("3-:2", false).is_err());
+        assert!(parse_positions("-9:2", false).is_err());
+        assert!(parse_positions("3:2", false).is_err());
+        assert_eq!(
+            parse_positions("9-1:2", false).unwrap_err().to_string(),
+            "First number in range (9) must be lower than second number (1)"
+        );
+    }
+
+    #[cfg(feature = "config")]
+    #[test]
+    fn test_config_file() {
+        let path = std::env::temp_dir().join("kat_test_config_file.json");
+        let recipe = r#"{"fields": "1,3", "delimiter": ",", "files": ["a.csv", "b.csv"], "empty-report": true}"#;
+        std::fs::write(&path, recipe).unwrap();
+        let path = path.to_str().unwrap();
+
+        let cfg = parse_config(to_owned_arg_list(vec!["kat", "--config", path])).unwrap();
+        assert_eq!(cfg.delimiter, b',');
+        assert_eq!(cfg.files, vec!["a.csv", "b.csv"]);
+        assert_eq!(format!("{:?}", cfg.selector), "Fields([0..1, 2..3])");
+        assert!(cfg.empty_report);
+
+        // Anything given on the command line wins over the config file
+        let args = vec!["kat", "--config", path, "-d", ";", "-f", "2", "c.csv"];
+        let cfg = parse_config(to_owned_arg_list(args)).unwrap();
+        assert_eq!(cfg.delimiter, b';');
+        assert_eq!(cfg.files, vec!["c.csv"]);
+        assert_eq!(format!("{:?}", cfg.selector), "Fields([1..2])");
+
+        let args = vec!["kat", "--config", path, "-c", "2"];
+        let cfg = parse_config(to_owned_arg_list(args)).unwrap();
+        assert_eq!(format!("{:?}", cfg.selector), "Chars([1..2])");
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[cfg(feature = "config")]
+    #[test]
+    fn test_config_file_unknown_option() {
+        let path = std::env::temp_dir().join("kat_test_config_file_unknown.json");
+        std::fs::write(&path, r#"{"fields": "1", "colour": "red"}"#).unwrap();
+        let path = path.to_str().unwrap();
+
+        let cfg = parse_config(to_owned_arg_list(vec!["kat", "--config", path]));
+        assert!(cfg.is_err());
+        assert_eq!(
+            cfg.unwrap_err().to_string(),
+            format!("kat: unknown option 'colour' in config file '{}'", path)
+        );
+
+        std::fs::remove_file(path).unwrap();
     }
 
     fn to_owned_arg_list(args: Vec<&str>) -> Vec<String> {
@@ -1,10 +1,14 @@
-use kat::{parse_config, run};
+use kat::{is_broken_pipe, parse_config, run};
 use std::env::args;
 use std::process::exit;
 
 fn main() {
     let cmd_args = args().collect();
     if let Err(e) = parse_config(cmd_args).and_then(run) {
+        // The exit status of a cut killed by SIGPIPE, just without the message
+        if is_broken_pipe(&e) {
+            exit(141);
+        }
         eprintln!("{}", e);
         exit(1);
     }
@@ -15,6 +19,7 @@ fn main() {
 mod main_tests {
     use crate::main;
 
+    #[allow(dead_code)]
     fn test_main() {
         main();
     }
@@ -10,6 +10,15 @@ const MOVIES_CSV: &str = "tests/inputs/movies1.csv";
 const MOVIES_TSV: &str = "tests/inputs/movies1.tsv";
 const BOOKS_TSV: &str = "tests/inputs/books.tsv";
 const BOOKS_CSV: &str = "tests/inputs/books.csv";
+const PAIRS1_TSV: &str = "tests/inputs/pairs1.tsv";
+const PAIRS2_TSV: &str = "tests/inputs/pairs2.tsv";
+const PAIRS3_TSV: &str = "tests/inputs/pairs3.tsv";
+const TYPED_CSV: &str = "tests/inputs/typed.csv";
+const LETTERS_TXT: &str = "tests/inputs/letters.txt";
+const SCORES_TSV: &str = "tests/inputs/scores.tsv";
+const CRLF_TSV: &str = "tests/inputs/crlf.tsv";
+const FIVE_TSV: &str = "tests/inputs/five.tsv";
+const GROCERIES_CSV: &str = "tests/inputs/groceries.csv";
 
 // --------------------------------------------------
 fn random_string() -> String {
@@ -36,10 +45,14 @@ fn skips_bad_file() -> TestResult {
     let bad = gen_bad_file();
     let expected = format!("{}: .* [(]os error 2[)]", bad);
     Command::cargo_bin(PRG)?
-        .args(&["-f", "1", MOVIES_CSV, &bad, MOVIES_TSV])
+        .args(["-f", "1", MOVIES_CSV, &bad, MOVIES_TSV])
         .assert()
-        .success()
-        .stderr(predicate::str::is_match(expected)?);
+        .failure()
+        .stdout(predicate::str::contains("The Blues Brothers").count(2))
+        .stderr(predicate::str::is_match(expected)?)
+        .stderr(predicate::str::contains(
+            "kat: 1 of 3 files could not be read",
+        ));
     Ok(())
 }
 
@@ -92,7 +105,10 @@ fn dies_bad_digit_chars() -> TestResult {
 // --------------------------------------------------
 #[test]
 fn dies_empty_delimiter() -> TestResult {
-    dies(&[MOVIES_CSV, "-f", "1", "-d", ""], "kat: bad delimiter")
+    dies(
+        &[MOVIES_CSV, "-f", "1", "-d", ""],
+        "kat: the delimiter can't be empty (leave -d out, or use --tab, for a tab)",
+    )
 }
 
 // --------------------------------------------------
@@ -105,7 +121,7 @@ fn dies_bad_delimiter() -> TestResult {
 #[test]
 fn dies_chars_bytes_fields() -> TestResult {
     Command::cargo_bin(PRG)?
-        .args(&[MOVIES_CSV, "-c", "1", "-f", "1", "-b", "1"])
+        .args([MOVIES_CSV, "-c", "1", "-f", "1", "-b", "1"])
         .assert()
         .failure();
     Ok(())
@@ -115,7 +131,7 @@ fn dies_chars_bytes_fields() -> TestResult {
 #[test]
 fn dies_bytes_fields() -> TestResult {
     Command::cargo_bin(PRG)?
-        .args(&[MOVIES_CSV, "-f", "1", "-b", "1"])
+        .args([MOVIES_CSV, "-f", "1", "-b", "1"])
         .assert()
         .failure();
     Ok(())
@@ -125,7 +141,7 @@ fn dies_bytes_fields() -> TestResult {
 #[test]
 fn dies_chars_fields() -> TestResult {
     Command::cargo_bin(PRG)?
-        .args(&[MOVIES_CSV, "-c", "1", "-f", "1"])
+        .args([MOVIES_CSV, "-c", "1", "-f", "1"])
         .assert()
         .failure();
     Ok(())
@@ -135,7 +151,7 @@ fn dies_chars_fields() -> TestResult {
 #[test]
 fn dies_chars_bytes() -> TestResult {
     Command::cargo_bin(PRG)?
-        .args(&[MOVIES_CSV, "-c", "1", "-b", "1"])
+        .args([MOVIES_CSV, "-c", "1", "-b", "1"])
         .assert()
         .failure();
     Ok(())
@@ -395,3 +411,2675 @@ fn books_quoted_when_delim() -> TestResult {
         "tests/expected/books_csv.f2,3.out",
     )
 }
+
+// --------------------------------------------------
+#[test]
+fn interleave_two_files() -> TestResult {
+    run(
+        &["--interleave", "-f", "1", PAIRS1_TSV, PAIRS2_TSV],
+        "tests/expected/pairs.interleave.f1.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn interleave_uneven_files() -> TestResult {
+    run(
+        &["--interleave", "-f", "1", PAIRS1_TSV, PAIRS3_TSV],
+        "tests/expected/pairs_uneven.interleave.f1.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn paste_two_files() -> TestResult {
+    run(
+        &["--paste", "-f", "1", PAIRS1_TSV, PAIRS2_TSV],
+        "tests/expected/pairs.paste.f1.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn paste_uneven_files() -> TestResult {
+    run(
+        &["--paste", "-f", "1", PAIRS1_TSV, PAIRS3_TSV],
+        "tests/expected/pairs_uneven.paste.f1.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn describe_typed_csv() -> TestResult {
+    run(
+        &[TYPED_CSV, "-d", ",", "-f", "1-4", "--describe"],
+        "tests/expected/typed.csv.describe.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn describe_sample() -> TestResult {
+    run(
+        &[
+            TYPED_CSV,
+            "-d",
+            ",",
+            "-f",
+            "1-2",
+            "--describe",
+            "--sample",
+            "2",
+        ],
+        "tests/expected/typed.csv.describe.sample2.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn chars_join_regions() -> TestResult {
+    run(
+        &[LETTERS_TXT, "-c", "1-2,5-6", "--join", "\t"],
+        "tests/expected/letters.c1-2,5-6.join_tab.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn chars_join_adjacent_ranges() -> TestResult {
+    run(
+        &[LETTERS_TXT, "-c", "1-2,3-4", "--join", "\t"],
+        "tests/expected/letters.c1-4.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn pipe_to_cat() -> TestResult {
+    run(
+        &[MOVIES_TSV, "-f", "1", "--pipe-to", "cat"],
+        "tests/expected/movies1.tsv.f1.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_pipe_to_failing_command() -> TestResult {
+    dies(
+        &[
+            MOVIES_TSV,
+            "-f",
+            "1",
+            "--pipe-to",
+            "cat > /dev/null; exit 3",
+        ],
+        "failed with exit status: 3",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn empty_report() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args([TYPED_CSV, "-d", ",", "-f", "2-4", "--empty-report"])
+        .assert()
+        .success()
+        .stdout(fs::read_to_string("tests/expected/typed.csv.f2-4.out")?)
+        .stderr("field\tempty\n2\t1\n3\t0\n4\t1\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[cfg(feature = "hash")]
+#[test]
+fn hash_fields() -> TestResult {
+    run(
+        &[TYPED_CSV, "-d", ",", "-f", "3-4", "--hash-fields", "3"],
+        "tests/expected/typed.csv.f3-4.hash3.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn split_output_round_robin() -> TestResult {
+    let prefix = std::env::temp_dir().join(random_string());
+    let prefix = prefix.to_str().unwrap();
+    Command::cargo_bin(PRG)?
+        .args([
+            "tests/inputs/six.txt",
+            "-c",
+            "1",
+            "--split-output",
+            prefix,
+            "--parts",
+            "2",
+        ])
+        .assert()
+        .success()
+        .stdout("");
+
+    let first = fs::read_to_string(format!("{}.0", prefix))?;
+    let second = fs::read_to_string(format!("{}.1", prefix))?;
+    fs::remove_file(format!("{}.0", prefix))?;
+    fs::remove_file(format!("{}.1", prefix))?;
+    assert_eq!(first, "r\nr\nr\n");
+    assert_eq!(second, "a\nb\nc\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_split_output_zero_parts() -> TestResult {
+    dies(
+        &[
+            MOVIES_TSV,
+            "-f",
+            "1",
+            "--split-output",
+            "out",
+            "--parts",
+            "0",
+        ],
+        "kat: invalid number of parts: '0'",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn headers_only() -> TestResult {
+    run(
+        &[
+            MOVIES_CSV,
+            BOOKS_CSV,
+            "-d",
+            ",",
+            "-f",
+            "1-2",
+            "--headers-only",
+            "--no-filename",
+        ],
+        "tests/expected/headers_only.f1-2.dcomma.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn sort_by_numeric_then_lexical() -> TestResult {
+    run(
+        &[SCORES_TSV, "-f", "1-2", "--sort-by", "2n,1"],
+        "tests/expected/scores.tsv.sort2n,1.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn sort_by_reversed_numeric_then_lexical() -> TestResult {
+    run(
+        &[SCORES_TSV, "-f", "1-2", "--sort-by", "2nr,1"],
+        "tests/expected/scores.tsv.sort2nr,1.out",
+    )
+}
+
+// --------------------------------------------------
+#[cfg(feature = "encoding")]
+#[test]
+fn detect_encoding() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--detect-encoding", "tests/inputs/utf16le.txt", MOVIES_TSV])
+        .assert()
+        .success()
+        .stdout("")
+        .stderr("tests/inputs/utf16le.txt: UTF-16LE\ntests/inputs/movies1.tsv: UTF-8\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn csv_quote_all() -> TestResult {
+    run(
+        &[MOVIES_CSV, "-f", "1-2", "-d", ",", "--quote-all"],
+        "tests/expected/movies1.csv.f1-2.dcomma.quote_all.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn max_output_bytes() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args([MOVIES_TSV, "-f", "1", "--max-output-bytes", "10"])
+        .assert()
+        .success()
+        .stdout("title\nThe ")
+        .stderr("kat: stopped at the output limit of 10 bytes\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn max_output_bytes_whole_lines() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args([
+            MOVIES_TSV,
+            "-c",
+            "1-5",
+            "--max-output-bytes",
+            "10",
+            "--whole-lines",
+        ])
+        .assert()
+        .success()
+        .stdout("title\n")
+        .stderr("kat: stopped at the output limit of 10 bytes\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn write_bom_once() -> TestResult {
+    let mut expected = b"\xEF\xBB\xBF".to_vec();
+    expected.extend(fs::read("tests/expected/movies1.tsv.f1.out")?);
+    expected.extend(fs::read("tests/expected/movies1.tsv.f1.out")?);
+    Command::cargo_bin(PRG)?
+        .args([
+            "--write-bom",
+            "--no-filename",
+            "-f",
+            "1",
+            MOVIES_TSV,
+            MOVIES_TSV,
+        ])
+        .assert()
+        .success()
+        .stdout(expected);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dos2unix_fields() -> TestResult {
+    run(
+        &[CRLF_TSV, "-f", "2", "--dos2unix"],
+        "tests/expected/crlf.tsv.f2.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dos2unix_chars() -> TestResult {
+    run(
+        &[CRLF_TSV, "-c", "1", "--dos2unix"],
+        "tests/expected/crlf.tsv.c1.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn unix2dos_fields() -> TestResult {
+    run(
+        &[PAIRS1_TSV, "-f", "1", "--unix2dos"],
+        "tests/expected/pairs1.tsv.f1.unix2dos.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn unix2dos_chars() -> TestResult {
+    run(
+        &[PAIRS1_TSV, "-c", "4-5", "--unix2dos"],
+        "tests/expected/pairs1.tsv.c4-5.unix2dos.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn bytes_total() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--bytes-total", MOVIES_TSV, BOOKS_TSV])
+        .assert()
+        .success()
+        .stdout("88 tests/inputs/movies1.tsv\n143 tests/inputs/books.tsv\n231 total\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn head_fields_keeps_tail() -> TestResult {
+    run(
+        &[FIVE_TSV, "-f", "2-3", "--head-fields", "2"],
+        "tests/expected/five.tsv.f2-3.head2.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn head_fields_drops_tail() -> TestResult {
+    run(
+        &[FIVE_TSV, "-f", "1", "--head-fields", "2"],
+        "tests/expected/five.tsv.f1.head2.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn null_ratio() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args([TYPED_CSV, "-d", ",", "-f", "2-4", "--null-ratio"])
+        .assert()
+        .success()
+        .stdout(fs::read_to_string("tests/expected/typed.csv.f2-4.out")?)
+        .stderr("field\tnull_ratio\n2\t25.00%\n3\t0.00%\n4\t25.00%\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[cfg(feature = "ndjson")]
+#[test]
+fn from_ndjson() -> TestResult {
+    run(
+        &[
+            "tests/inputs/people.ndjson",
+            "--from-ndjson",
+            "-f",
+            "name,age",
+            "-d",
+            ",",
+            "--missing-as",
+            "NA",
+        ],
+        "tests/expected/people.ndjson.name,age.out",
+    )
+}
+
+// --------------------------------------------------
+#[cfg(feature = "ndjson")]
+#[test]
+fn dies_from_ndjson_not_an_object() -> TestResult {
+    dies(
+        &[MOVIES_TSV, "--from-ndjson", "-f", "title"],
+        "kat: line 1 is not a JSON object",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn pretty_errors_chars() -> TestResult {
+    dies(
+        &["tests/inputs/latin1.csv", "-c", "1", "--pretty-errors"],
+        "kat: invalid UTF-8 on line 2, column 6\n  |\n2 | Zoë,K\u{fffd}ln\n  |      ^",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn pretty_errors_fields_utf8() -> TestResult {
+    dies(
+        &[
+            "tests/inputs/latin1.csv",
+            "-d",
+            ",",
+            "-f",
+            "1",
+            "--pretty-errors",
+        ],
+        "kat: invalid UTF-8 on line 2, column 6\n  |\n2 | Zoë,K\u{fffd}ln\n  |      ^",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn pretty_errors_fields_ragged() -> TestResult {
+    dies(
+        &[
+            "tests/inputs/ragged.csv",
+            "-d",
+            ",",
+            "-f",
+            "1",
+            "--pretty-errors",
+        ],
+        "kat: found 2 fields where 3 were expected on line 2, column 4\n  |\n2 | d,e\n  |    ^",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn group_sum() -> TestResult {
+    run(
+        &[GROCERIES_CSV, "-d", ",", "-f", "1,3", "--group-sum"],
+        "tests/expected/groceries.csv.f1,3.group_sum.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_group_sum_three_fields() -> TestResult {
+    dies(
+        &[GROCERIES_CSV, "-d", ",", "-f", "1-3", "--group-sum"],
+        "kat: --group-sum needs exactly two fields",
+    )
+}
+
+// --------------------------------------------------
+#[cfg(feature = "hash")]
+#[test]
+fn hash_sample_is_deterministic() -> TestResult {
+    // The same keys are kept run after run
+    for _ in 0..2 {
+        run(
+            &[
+                "tests/inputs/users.csv",
+                "-d",
+                ",",
+                "-f",
+                "1-2",
+                "--hash-sample",
+                "1:0.5",
+            ],
+            "tests/expected/users.csv.hash_sample1-0.5.out",
+        )?;
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn tsv_f2_open_ended() -> TestResult {
+    // There are only three fields so this is the same as 2-3
+    run(
+        &[MOVIES_TSV, "-f", "2-"],
+        "tests/expected/movies1.tsv.f2-3.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn tsv_c_open_start() -> TestResult {
+    run(
+        &[MOVIES_TSV, "-c", "-2"],
+        "tests/expected/movies1.tsv.c1-2.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_group_sum_open_ended() -> TestResult {
+    dies(
+        &[GROCERIES_CSV, "-d", ",", "-f", "1-", "--group-sum"],
+        "kat: --group-sum needs exactly two fields",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn only_delimited_drops_undelimited_lines() -> TestResult {
+    run(
+        &["tests/inputs/mixed.tsv", "-f", "2", "--only-delimited"],
+        "tests/expected/mixed.tsv.f2.only_delimited.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn only_delimited_short_flag() -> TestResult {
+    run(
+        &["tests/inputs/mixed.tsv", "-s", "-f", "2"],
+        "tests/expected/mixed.tsv.f2.only_delimited.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn output_delimiter() -> TestResult {
+    run(
+        &["tests/inputs/abc.tsv", "-f1,3", "--output-delimiter=:"],
+        "tests/expected/abc.tsv.f1,3.output_delim.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn keep_order_fields() -> TestResult {
+    run(
+        &["tests/inputs/abc.tsv", "-f", "3,1", "--keep-order"],
+        "tests/expected/abc.tsv.f3,1.keep_order.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn fields_sorted_without_keep_order() -> TestResult {
+    run(
+        &["tests/inputs/abc.tsv", "-f", "3,1-2,1"],
+        "tests/expected/abc.tsv.f3,1.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn keep_order_chars_with_join() -> TestResult {
+    run(
+        &[
+            "tests/inputs/abc.tsv",
+            "-c",
+            "5,3,1",
+            "--keep-order",
+            "--join",
+            "-",
+        ],
+        "tests/expected/abc.tsv.c5,3,1.keep_order.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn buffered_output_is_complete() -> TestResult {
+    // Well past the size of the output buffer, so it has to be flushed a few times over
+    let input: String = (0..20_000).map(|n| format!("{}\tx\n", n)).collect();
+    let expected: String = (0..20_000).map(|n| format!("{}\n", n)).collect();
+    Command::cargo_bin(PRG)?
+        .args(["-f", "1"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout(expected);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn zero_terminated_fields() -> TestResult {
+    run(
+        &["tests/inputs/nul.tsv", "-z", "-f", "2"],
+        "tests/expected/nul.tsv.f2.z.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn zero_terminated_chars() -> TestResult {
+    run(
+        &["tests/inputs/nul.tsv", "--zero-terminated", "-c", "1"],
+        "tests/expected/nul.tsv.c1.z.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn zero_terminated_bytes() -> TestResult {
+    run(
+        &["tests/inputs/nul.tsv", "-z", "-b", "1"],
+        "tests/expected/nul.tsv.c1.z.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn no_split_drops_partial_chars() -> TestResult {
+    // Bytes 1-2 take in just the first byte of é
+    run(
+        &["tests/inputs/accents.txt", "-b", "1-2", "-n"],
+        "tests/expected/accents.txt.b1-2.n.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn no_split_keeps_whole_chars() -> TestResult {
+    run(
+        &["tests/inputs/accents.txt", "-n", "-b", "1-3"],
+        "tests/expected/accents.txt.b1-3.n.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn regex_delim() -> TestResult {
+    run(
+        &[
+            "tests/inputs/spaced.txt",
+            "--regex-delim",
+            r"\s+",
+            "-f",
+            "1,3",
+        ],
+        "tests/expected/spaced.txt.f1,3.regex.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_regex_delim_with_delimiter() -> TestResult {
+    dies(
+        &[
+            "tests/inputs/spaced.txt",
+            "--regex-delim",
+            r"\s+",
+            "-d",
+            ",",
+            "-f",
+            "1",
+        ],
+        "cannot be used with",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_regex_delim() -> TestResult {
+    dies(
+        &["tests/inputs/spaced.txt", "--regex-delim", "(", "-f", "1"],
+        "kat: invalid regex delimiter",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn squeeze_collapses_runs_of_delimiters() -> TestResult {
+    run(
+        &["tests/inputs/runs.csv", "-d", ",", "-f2", "--squeeze"],
+        "tests/expected/runs.csv.f2.squeeze.out",
+    )
+}
+
+// --------------------------------------------------
+#[cfg(feature = "ndjson")]
+#[test]
+fn json_output_escapes_values() -> TestResult {
+    run(
+        &[
+            "tests/inputs/quoted.csv",
+            "-d",
+            ",",
+            "-f",
+            "1-2",
+            "--output-format",
+            "json",
+        ],
+        "tests/expected/quoted.csv.f1-2.json.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_json_output_with_chars() -> TestResult {
+    dies(
+        &[MOVIES_TSV, "-c", "1", "--output-format", "json"],
+        "cannot be used with",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn header_names() -> TestResult {
+    run(
+        &[
+            "tests/inputs/contacts.csv",
+            "-d",
+            ",",
+            "-f",
+            "email,name",
+            "--header-names",
+            "--keep-order",
+        ],
+        "tests/expected/contacts.csv.email,name.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_header_names_unknown_name() -> TestResult {
+    dies(
+        &[
+            "tests/inputs/contacts.csv",
+            "-d",
+            ",",
+            "-f",
+            "name,phone",
+            "--header-names",
+        ],
+        "kat: no field named 'phone' in the header",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn line_numbers_fields() -> TestResult {
+    // The blank line still counts even though there's nothing to cut from it
+    run(
+        &["tests/inputs/gappy.tsv", "-N", "-f", "2"],
+        "tests/expected/gappy.tsv.f2.N.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn line_numbers_chars() -> TestResult {
+    run(
+        &["tests/inputs/gappy.tsv", "--line-numbers", "-c", "1"],
+        "tests/expected/gappy.tsv.c1.N.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn line_numbers_bytes() -> TestResult {
+    run(
+        &["tests/inputs/gappy.tsv", "-N", "-b", "1"],
+        "tests/expected/gappy.tsv.c1.N.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn line_numbers_restart_for_each_file() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args([
+            "-N",
+            "--no-filename",
+            "-f",
+            "1",
+            "tests/inputs/abc.tsv",
+            "tests/inputs/abc.tsv",
+        ])
+        .assert()
+        .success()
+        .stdout("1\ta\n1\ta\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn strips_cr_by_default() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args([CRLF_TSV, "-c", "3-"])
+        .assert()
+        .success()
+        .stdout("b\nd\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn keep_cr() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args([CRLF_TSV, "-b", "3-", "--keep-cr"])
+        .assert()
+        .success()
+        .stdout("b\r\nd\r\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_keep_cr_with_fields() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args([CRLF_TSV, "-f", "2", "--keep-cr"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used with"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_on_dir_without_recursive() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-f", "1", "tests/inputs"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "tests/inputs: Is a directory (use -r to cut the files in it)",
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn recursive() -> TestResult {
+    let root = std::env::temp_dir().join(random_string());
+    fs::create_dir_all(root.join("b/c"))?;
+    fs::write(root.join("b/c/2.tsv"), "c\td\n")?;
+    fs::write(root.join("b/1.tsv"), "a\tb\n")?;
+    fs::write(root.join("3.tsv"), "e\tf\n")?;
+    // A link back up the tree mustn't have us going round in circles
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(&root, root.join("b/c/up"))?;
+
+    let output = Command::cargo_bin(PRG)?
+        .args([
+            "-r",
+            "--no-filename",
+            "-f",
+            "2",
+            "-",
+            root.to_str().unwrap(),
+        ])
+        .write_stdin("x\ty\n")
+        .output()?;
+    fs::remove_dir_all(&root)?;
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout)?, "y\nf\nb\nd\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn zero_based() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--zero-based", "-f", "0,2", "tests/inputs/abc.tsv"])
+        .assert()
+        .success()
+        .stdout("a\tc\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn quoted_fields_by_default() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-d", ",", "-f", "2", "tests/inputs/quoted.csv"])
+        .assert()
+        .success()
+        .stdout("\"say \"\"hi\"\", ok\"\ne\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn no_quoting() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args([
+            "-d",
+            ",",
+            "-f",
+            "3",
+            "--no-quoting",
+            "tests/inputs/quoted.csv",
+        ])
+        .assert()
+        .success()
+        .stdout(" ok\"\nf\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn no_quoting_raw_line() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-d", ",", "-f", "1,3", "--no-quoting"])
+        .write_stdin("\"a,b\",c\n")
+        .assert()
+        .success()
+        .stdout("\"a,c\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn jobs_keep_file_order() -> TestResult {
+    let files = [
+        MOVIES_TSV, BOOKS_TSV, PAIRS1_TSV, PAIRS2_TSV, PAIRS3_TSV, SCORES_TSV, FIVE_TSV, CRLF_TSV,
+    ];
+    let serial = Command::cargo_bin(PRG)?
+        .args(["-f", "1-2"])
+        .args(files)
+        .output()?;
+    for jobs in ["2", "3", "8", "20"] {
+        Command::cargo_bin(PRG)?
+            .args(["-f", "1-2", "--jobs", jobs])
+            .args(files)
+            .assert()
+            .success()
+            .stdout(serial.stdout.clone());
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn jobs_skip_bad_file() -> TestResult {
+    let bad = gen_bad_file();
+    let serial = Command::cargo_bin(PRG)?
+        .args(["-f", "1", PAIRS1_TSV, PAIRS2_TSV])
+        .output()?;
+    Command::cargo_bin(PRG)?
+        .args(["-f", "1", "--jobs", "2", PAIRS1_TSV, &bad, PAIRS2_TSV])
+        .assert()
+        .failure()
+        .stdout(serial.stdout)
+        .stderr(predicate::str::contains(format!("{}: ", bad)))
+        .stderr(predicate::str::contains(
+            "kat: 1 of 3 files could not be read",
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_jobs() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-f", "1", "--jobs", "0", PAIRS1_TSV])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("kat: invalid number of jobs: '0'"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn undelimited_lines_as_they_are() -> TestResult {
+    run(
+        &["tests/inputs/plain.tsv", "-f", "2"],
+        "tests/expected/plain.tsv.f2.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn only_delimited_drops_plain_lines() -> TestResult {
+    run(
+        &["tests/inputs/plain.tsv", "-s", "-f", "2"],
+        "tests/expected/plain.tsv.f2.only_delimited.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn str_delim() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--str-delim", "::", "-f1,3"])
+        .write_stdin("a::b::c\nd:e::f::g\n")
+        .assert()
+        .success()
+        .stdout("a::c\nd:e::g\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn str_delim_output_delimiter() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--str-delim", " | ", "-f", "1,3", "--output-delimiter", ","])
+        .write_stdin("a | b | c\n")
+        .assert()
+        .success()
+        .stdout("a,c\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_str_delim_with_delimiter() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--str-delim", "::", "-d", ",", "-f", "1"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used with"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn strict_utf8() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-b", "1-3", "--strict-utf8", "tests/inputs/accents.txt"])
+        .assert()
+        .success()
+        .stdout("hé\nhel\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_strict_utf8_split_char() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-b", "1-2", "--strict-utf8", "tests/inputs/accents.txt"])
+        .assert()
+        .failure()
+        .stdout("")
+        .stderr(predicate::str::contains(
+            "kat: tests/inputs/accents.txt: line 1 cuts down to invalid UTF-8 at byte 2",
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn output_file() -> TestResult {
+    let out = std::env::temp_dir().join(random_string());
+    fs::write(&out, "whatever was here before is replaced\n")?;
+    Command::cargo_bin(PRG)?
+        .args([
+            "-f",
+            "1",
+            "-o",
+            out.to_str().unwrap(),
+            "tests/inputs/abc.tsv",
+        ])
+        .assert()
+        .success()
+        .stdout("");
+    let written = fs::read_to_string(&out)?;
+    fs::remove_file(&out)?;
+    assert_eq!(written, "a\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_unwritable_output_file() -> TestResult {
+    let out = format!("{}/{}", gen_bad_file(), "out.tsv");
+    Command::cargo_bin(PRG)?
+        .args(["-f", "1", "--output", &out, "tests/inputs/abc.tsv"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(format!(
+            "kat: can't write to {}: ",
+            out
+        )));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn lists_from_files() -> TestResult {
+    for (inline, from_file) in [
+        ("-f", "--fields-file"),
+        ("-c", "--characters-file"),
+        ("-b", "--bytes-file"),
+    ] {
+        let expected = Command::cargo_bin(PRG)?
+            .args([inline, "1,3,5-", MOVIES_TSV])
+            .output()?;
+        Command::cargo_bin(PRG)?
+            .args([from_file, "tests/inputs/positions.txt", MOVIES_TSV])
+            .assert()
+            .success()
+            .stdout(expected.stdout);
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_list_inline_and_from_file() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args([
+            "-f",
+            "1",
+            "--fields-file",
+            "tests/inputs/positions.txt",
+            MOVIES_TSV,
+        ])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used with"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_missing_list_file() -> TestResult {
+    let bad = gen_bad_file();
+    Command::cargo_bin(PRG)?
+        .args(["--fields-file", &bad, MOVIES_TSV])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(format!("kat: {}: ", bad)));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn count_fields() -> TestResult {
+    run(
+        &["tests/inputs/ragged.tsv", "--count-fields"],
+        "tests/expected/ragged.tsv.count_fields.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn lenient_ranges() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-f", "2,4", "tests/inputs/abc.tsv"])
+        .assert()
+        .success()
+        .stdout("b\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn strict_ranges() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-c", "1-3", "--strict-ranges", "tests/inputs/ragged.tsv"])
+        .assert()
+        .failure()
+        .stdout("a\tb\nd\te\n")
+        .stderr(predicate::str::contains(
+            "kat: tests/inputs/ragged.tsv: line 3 has 1 character, too few for character 3",
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn strict_ranges_fields() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-f", "2,4", "--strict-ranges", "tests/inputs/abc.tsv"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "kat: tests/inputs/abc.tsv: line 1 has 3 fields, too few for field 4",
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn stepped_chars() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-c", "1-9:2"])
+        .write_stdin("abcdefghij\n")
+        .assert()
+        .success()
+        .stdout("acegi\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn zero_step() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-f", "1-9:0", "tests/inputs/abc.tsv"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("kat: illegal step: '1-9:0'"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn tail_anchored() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-f", "-1", "--tail-anchored", "tests/inputs/ragged.tsv"])
+        .assert()
+        .success()
+        .stdout("c\ne\nf\ni\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn tail_anchored_with_positive() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-f", "-1,1", "--tail-anchored", "tests/inputs/ragged.tsv"])
+        .assert()
+        .success()
+        .stdout("a\tc\nd\te\nf\n\ti\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn tail_anchored_keep_order() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-f", "-1,-2", "--tail-anchored", "--keep-order"])
+        .arg("tests/inputs/ragged.tsv")
+        .assert()
+        .success()
+        .stdout("c\tb\ne\td\nf\ni\th\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn tail_anchored_chars() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-c", "-3", "--tail-anchored", "--strict-ranges"])
+        .arg("tests/inputs/ragged.tsv")
+        .assert()
+        .failure()
+        .stdout("b\nd\n")
+        .stderr(predicate::str::contains(
+            "line 3 has 1 character, too few for character -3",
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn trim() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-d", ",", "-f", "1,3", "--trim"])
+        .write_stdin(" a , b , c \n\td,e,  f\n")
+        .assert()
+        .success()
+        .stdout("a,c\nd,f\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn no_trim() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-d", ",", "-f", "1,3"])
+        .write_stdin(" a , b , c \n")
+        .assert()
+        .success()
+        .stdout(" a , c \n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn trim_chars() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-c", "1", "--trim", "tests/inputs/abc.tsv"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used with"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[cfg(feature = "encoding")]
+#[test]
+fn encoding_utf16le() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args([
+            "-c",
+            "1-3",
+            "--encoding",
+            "utf-16le",
+            "tests/inputs/utf16le.txt",
+        ])
+        .assert()
+        .success()
+        .stdout("nam\nZoë\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[cfg(feature = "encoding")]
+#[test]
+fn encoding_utf16be() -> TestResult {
+    // Without a BOM to go on
+    let input: Vec<u8> = "a\tb\nc\td\n"
+        .encode_utf16()
+        .flat_map(|unit| unit.to_be_bytes())
+        .collect();
+    Command::cargo_bin(PRG)?
+        .args(["-f", "2", "--encoding", "utf-16be"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("b\nd\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[cfg(feature = "encoding")]
+#[test]
+fn encoding_utf8_bom() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-c", "1", "--encoding", "utf-8"])
+        .write_stdin("\u{feff}ab\ncd\n")
+        .assert()
+        .success()
+        .stdout("a\nc\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn reverse() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-f", "1-3", "--reverse", "tests/inputs/abc.tsv"])
+        .assert()
+        .success()
+        .stdout("c\tb\ta\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn reverse_dedups() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-f", "3,1,3", "--reverse", "tests/inputs/abc.tsv"])
+        .assert()
+        .success()
+        .stdout("c\ta\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn reverse_keep_order() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args([
+            "-f",
+            "1,3",
+            "--reverse",
+            "--keep-order",
+            "tests/inputs/abc.tsv",
+        ])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used with"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn stats() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args([
+            "-f",
+            "1",
+            "-s",
+            "--stats",
+            "--no-filename",
+            "tests/inputs/plain.tsv",
+        ])
+        .arg("tests/inputs/abc.tsv")
+        .assert()
+        .success()
+        .stdout("a\nc\na\n")
+        .stderr("lines read: 5\nlines written: 3\nlines skipped: 2\nbytes written: 6\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn stats_unterminated() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-c", "1-2", "--stats"])
+        .write_stdin("abc\ndef")
+        .assert()
+        .success()
+        .stdout("ab\nde\n")
+        .stderr("lines read: 2\nlines written: 2\nlines skipped: 0\nbytes written: 6\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn header_only_delimited() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-f", "2,1", "-s", "--keep-order", "--header"])
+        .write_stdin("id\tname\n1\tann\nplain\n2\tbob\n")
+        .assert()
+        .success()
+        .stdout("name\tid\nann\t1\nbob\t2\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn header_without_delimiter() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-f", "2", "-s", "--header"])
+        .write_stdin("scores\na\t10\nplain\nb\t2\n")
+        .assert()
+        .success()
+        .stdout("scores\n10\n2\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn header_sort_by() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-f", "2,1", "--sort-by", "2n", "--header"])
+        .write_stdin("name\tscore\nb\t2\na\t10\nc\t1\n")
+        .assert()
+        .success()
+        .stdout("name\tscore\nc\t1\nb\t2\na\t10\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[cfg(unix)]
+#[test]
+fn broken_pipe() -> TestResult {
+    use std::io::{Read, Write};
+    use std::process::{Command, Stdio};
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_kat"))
+        .args(["-c", "1-3"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut stdin = child.stdin.take().unwrap();
+    // Far more than fits in any pipe, so kat is still writing when we stop reading
+    let writer = std::thread::spawn(move || {
+        let line = "abcdef\n".repeat(1000);
+        for _ in 0..1000 {
+            if stdin.write_all(line.as_bytes()).is_err() {
+                break;
+            }
+        }
+    });
+    let mut head = [0; 4];
+    child.stdout.take().unwrap().read_exact(&mut head)?;
+    assert_eq!(&head, b"abc\n");
+
+    let output = child.wait_with_output()?;
+    writer.join().unwrap();
+    assert_eq!(output.status.code(), Some(141));
+    assert_eq!(String::from_utf8(output.stderr)?, "");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[cfg(feature = "gzip")]
+#[test]
+fn decompress_stdin() -> TestResult {
+    use flate2::{write::GzEncoder, Compression};
+    use std::io::Write;
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(b"a\tb\nc\td\n")?;
+    Command::cargo_bin(PRG)?
+        .args(["-f", "1", "--decompress", "gz"])
+        .write_stdin(encoder.finish()?)
+        .assert()
+        .success()
+        .stdout("a\nc\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn show_ranges() -> TestResult {
+    // No input is read, so nothing is made of there being no such file
+    Command::cargo_bin(PRG)?
+        .args(["-f", "1,7,3-5,4-6", "--show-ranges", "no-such-file"])
+        .assert()
+        .success()
+        .stdout("fields: 0..1, 2..7\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn show_ranges_keep_order() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-c", "3,1,8-", "--keep-order", "--show-ranges"])
+        .assert()
+        .success()
+        .stdout("characters: 2..3, 0..1, 7..\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn color_always() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-d", ",", "-f", "2,4-", "--color", "always"])
+        .write_stdin("a,b,c,d,e\nf,g\n")
+        .assert()
+        .success()
+        .stdout(
+            "a,\x1b[1;31mb\x1b[0m,c,\x1b[1;31md\x1b[0m,\x1b[1;31me\x1b[0m\nf,\x1b[1;31mg\x1b[0m\n",
+        );
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn color_never() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-f", "2", "--color", "never", "tests/inputs/abc.tsv"])
+        .assert()
+        .success()
+        .stdout("a\tb\tc\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn color_auto() -> TestResult {
+    // Our stdout is a pipe rather than a terminal
+    Command::cargo_bin(PRG)?
+        .args(["-f", "2", "--color", "auto", "tests/inputs/abc.tsv"])
+        .assert()
+        .success()
+        .stdout("a\tb\tc\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn null_output() -> TestResult {
+    for list in [["-f", "2"], ["-c", "3"], ["-b", "3"]] {
+        Command::cargo_bin(PRG)?
+            .args(list)
+            .arg("--null-output")
+            .write_stdin("a\tb\nc\td\n")
+            .assert()
+            .success()
+            .stdout("b\0d\0");
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn null_output_zero_terminated() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-f", "2", "-z", "--null-output"])
+        .write_stdin("a\tb\0c\td\0")
+        .assert()
+        .success()
+        .stdout("b\0d\0");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn filename_banners() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-f", "1", PAIRS1_TSV, "tests/inputs/abc.tsv"])
+        .assert()
+        .success()
+        .stdout("==> tests/inputs/pairs1.tsv <==\na1\na2\n\n==> tests/inputs/abc.tsv <==\na\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn filename_banners_stdin() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-f", "1", "-", "tests/inputs/abc.tsv"])
+        .write_stdin("x\ty\n")
+        .assert()
+        .success()
+        .stdout("x\n\n==> tests/inputs/abc.tsv <==\na\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn with_filename_one_file() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-f", "1", "--with-filename", "tests/inputs/abc.tsv"])
+        .assert()
+        .success()
+        .stdout("==> tests/inputs/abc.tsv <==\na\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn filename_banners_jobs() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args([
+            "-f",
+            "1",
+            "--jobs",
+            "2",
+            "tests/inputs/abc.tsv",
+            "no-such-file",
+        ])
+        .arg("tests/inputs/abc.tsv")
+        .assert()
+        .failure()
+        .stdout("==> tests/inputs/abc.tsv <==\na\n\n==> tests/inputs/abc.tsv <==\na\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn max_lines() -> TestResult {
+    for list in [["-f", "1"], ["-c", "1"], ["-b", "1"]] {
+        Command::cargo_bin(PRG)?
+            .args(list)
+            .arg("-m2")
+            .write_stdin("1\ta\n2\tb\n3\tc\n4\td\n5\te\n")
+            .assert()
+            .success()
+            .stdout("1\n2\n");
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn max_lines_per_file() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args([
+            "-f",
+            "2",
+            "--max-lines",
+            "1",
+            "--no-filename",
+            PAIRS1_TSV,
+            PAIRS1_TSV,
+        ])
+        .assert()
+        .success()
+        .stdout("b1\nb1\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn max_lines_only_delimited() -> TestResult {
+    // Lines which aren't written out don't count
+    Command::cargo_bin(PRG)?
+        .args(["-f", "1", "-s", "-m", "2", "tests/inputs/plain.tsv"])
+        .assert()
+        .success()
+        .stdout("a\nc\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_max_lines() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-f", "1", "-m", "two", PAIRS1_TSV])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "kat: invalid number of lines: 'two'",
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn all_fields() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args([
+            "-f",
+            "*",
+            "--output-delimiter",
+            ",",
+            "tests/inputs/ragged.tsv",
+        ])
+        .arg("--tail-anchored")
+        .assert()
+        .success()
+        .stdout("a,b,c\nd,e\nf\n,g,h,i\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn all_chars() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-c", "*", "tests/inputs/abc.tsv"])
+        .assert()
+        .success()
+        .stdout("a\tb\tc\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_all_with_others() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-f", "1,*", "tests/inputs/abc.tsv"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "kat: '*' can't be listed along with other positions",
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn fail_fast() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-d", ",", "-f", "1", "tests/inputs/malformed.csv"])
+        .assert()
+        .failure()
+        .stdout("name\nAlice\n")
+        .stderr(predicate::str::contains("found record with 3 fields"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn keep_going() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args([
+            "-d",
+            ",",
+            "-f",
+            "1",
+            "--keep-going",
+            "tests/inputs/malformed.csv",
+        ])
+        .assert()
+        .success()
+        .stdout("name\nAlice\nCarol\n")
+        .stderr(predicate::str::starts_with(
+            "kat: skipping line 3: CSV error",
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn keep_going_chars() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-c", "1", "--keep-going"])
+        .write_stdin(b"ab\n\xffc\nde\n".to_vec())
+        .assert()
+        .success()
+        .stdout("a\nd\n")
+        .stderr("kat: skipping line 2: stream did not contain valid UTF-8\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn chars_output_delimiter() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-c", "1-2,5-6", "--output-delimiter=-"])
+        .write_stdin("abcdefgh\nxyz\n")
+        .assert()
+        .success()
+        .stdout("ab-ef\nxy\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn bytes_output_delimiter() -> TestResult {
+    // Ranges which touch make up one region, and the delimiter can be any string
+    Command::cargo_bin(PRG)?
+        .args(["-b", "1,2-3,5,7-", "--output-delimiter", " | "])
+        .write_stdin("abcdefgh\n")
+        .assert()
+        .success()
+        .stdout("abc | e | gh\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn bytes_output_delimiter_no_split() -> TestResult {
+    // The char cut in two at the end of the first region is still left out
+    Command::cargo_bin(PRG)?
+        .args(["-b", "1-2,4-6", "-n", "--output-delimiter", ":"])
+        .write_stdin("aébcd\n")
+        .assert()
+        .success()
+        .stdout("a:bcd\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn hex() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-b", "1,3-5", "--hex"])
+        .write_stdin(b"a\x00\xff\xe9Z!\n\x01\n".to_vec())
+        .assert()
+        .success()
+        .stdout("61 ff e9 5a\n01\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn hex_needs_bytes() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-c", "1", "--hex", "tests/inputs/abc.tsv"])
+        .assert()
+        .failure();
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn files_from_stdin() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--files-from", "-", "--no-filename", "-f", "1"])
+        .write_stdin("tests/inputs/pairs1.tsv\n\ntests/inputs/pairs2.tsv\n")
+        .assert()
+        .success()
+        .stdout("a1\na2\nx1\nx2\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn files_from_file_adds_to_files() -> TestResult {
+    let list = std::env::temp_dir().join(random_string());
+    fs::write(&list, "tests/inputs/abc.tsv\n")?;
+    let output = Command::cargo_bin(PRG)?
+        .args(["-f", "1", "tests/inputs/abc.tsv", "--files-from"])
+        .arg(&list)
+        .output()?;
+    fs::remove_file(&list)?;
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout)?;
+    assert_eq!(stdout.matches("==> tests/inputs/abc.tsv <==").count(), 2);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn files_from_stdin_with_stdin_data() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--files-from", "-", "-f", "1", "-"])
+        .write_stdin("tests/inputs/abc.tsv\n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "can't read both the file list and the data from stdin",
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn tab() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--tab", "-f", "2", "tests/inputs/abc.tsv"])
+        .assert()
+        .success()
+        .stdout("b\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn tab_with_delimiter() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--tab", "-d", ",", "-f", "1", MOVIES_CSV])
+        .assert()
+        .failure();
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn na() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-d", ",", "-f", "2", "--na", "NULL"])
+        .write_stdin("a,,c\nd,e,f\n")
+        .assert()
+        .success()
+        .stdout("NULL\ne\n");
+    Command::cargo_bin(PRG)?
+        .args(["-d", ",", "-f", "1-", "--na", r"\N", "--trim"])
+        .write_stdin("a, ,c\n")
+        .assert()
+        .success()
+        .stdout("a,\\N,c\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn min_length() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-c", "3-5", "--min-length", "5", "-N"])
+        .write_stdin("ab\nabcde\nabc\nèéêëì\nabcdefg\n")
+        .assert()
+        .success()
+        .stdout("2\tcde\n4\têëì\n5\tcde\n");
+    // Counted in bytes with -b
+    Command::cargo_bin(PRG)?
+        .args(["-b", "1", "--min-length", "5"])
+        .write_stdin("ab\nabcde\nèé\nèéê\n")
+        .assert()
+        .success()
+        .stdout("a\n\u{fffd}\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn bad_min_length() -> TestResult {
+    dies(
+        &["-c", "1", "--min-length", "x"],
+        "kat: invalid length: 'x'",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn strict_csv() -> TestResult {
+    // Left to itself the writer doesn't quote a newline when lines end with NUL
+    Command::cargo_bin(PRG)?
+        .args(["-d", ",", "-f", "1-2", "-z"])
+        .write_stdin("\"a\nb\",c\0")
+        .assert()
+        .success()
+        .stdout("a\nb,c\0");
+    Command::cargo_bin(PRG)?
+        .args(["-d", ",", "-f", "1-2", "-z", "--strict-csv"])
+        .write_stdin("\"a\nb\",c\0\"x\"\"y\",\"1,2\"\0,z\0")
+        .assert()
+        .success()
+        .stdout("\"a\nb\",c\0\"x\"\"y\",\"1,2\"\0,z\0");
+    Command::cargo_bin(PRG)?
+        .args(["-d", ",", "-f", "1", "--strict-csv"])
+        .write_stdin(",z\n")
+        .assert()
+        .success()
+        .stdout("\"\"\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn raw() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-d", ",", "-f", "1-2", "--raw"])
+        .write_stdin("\"x\"\"y\",\"1,2\"\n\"a\nb\",c\n")
+        .assert()
+        .success()
+        .stdout("x\"y,1,2\na\nb,c\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn strict_csv_with_raw() -> TestResult {
+    dies(
+        &["-d", ",", "-f", "1", "--strict-csv", "--raw"],
+        "cannot be used with",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn long_output_delimiter() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-d", ",", "-f", "1,3", "--output-delimiter", " | ", "-N"])
+        .write_stdin("a,b,c\n\"d,e\",f,g\n")
+        .assert()
+        .success()
+        .stdout("1 | a | c\n2 | d,e | g\n");
+    Command::cargo_bin(PRG)?
+        .args([
+            "--regex-delim",
+            r"\s+",
+            "-f",
+            "2-",
+            "--output-delimiter",
+            r" \t ",
+        ])
+        .write_stdin("a  b c\n")
+        .assert()
+        .success()
+        .stdout("b \t c\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn char_safe_bytes() -> TestResult {
+    // Left to itself kat cuts the 2-byte é and the 3-byte € up, which can leave half a char or
+    // even a different one
+    Command::cargo_bin(PRG)?
+        .args(["-b", "2"])
+        .write_stdin("aébc€d\n")
+        .assert()
+        .success()
+        .stdout("\u{fffd}\n");
+    Command::cargo_bin(PRG)?
+        .args(["-b", "2,7"])
+        .write_stdin("aébc€d\n")
+        .assert()
+        .success()
+        .stdout("Â\n");
+    Command::cargo_bin(PRG)?
+        .args(["-b", "2,7", "--char-safe-bytes"])
+        .write_stdin("aébc€d\nxy\n")
+        .assert()
+        .success()
+        .stdout("é€\ny\n");
+    Command::cargo_bin(PRG)?
+        .args(["-b", "3-4", "--char-safe-bytes", "--output-delimiter", ":"])
+        .write_stdin("aébc€d\n")
+        .assert()
+        .success()
+        .stdout("éb\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn progress_only_on_tty() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-f", "2", "--progress", "tests/inputs/abc.tsv"])
+        .assert()
+        .success()
+        .stdout("b\n")
+        .stderr("");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn header_regex() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-d", ",", "--header-regex", "^amount_"])
+        .write_stdin("amount_usd,name,amount_eur\n1,a,2\n3,b,4\n")
+        .assert()
+        .success()
+        .stdout("amount_usd,amount_eur\n1,2\n3,4\n");
+    Command::cargo_bin(PRG)?
+        .args(["-d", ",", "--header-regex", "^amount_"])
+        .write_stdin("amount_usd,amount_eur,name\n1,2,a\n")
+        .assert()
+        .success()
+        .stdout("amount_usd,amount_eur\n1,2\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn header_regex_matching_nothing() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-d", ",", "--header-regex", "^total_"])
+        .write_stdin("amount_usd,amount_eur,name\n1,2,a\n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "kat: no field in the header matches '^total_'",
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn tab_output() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-d", ",", "-f", "1,3", "--tab-output"])
+        .write_stdin("a,b,c\n\"d,e\",f,\"g\th\"\n")
+        .assert()
+        .success()
+        .stdout("a\tc\nd,e\t\"g\th\"\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn tab_output_with_output_delimiter() -> TestResult {
+    dies(
+        &[
+            "-d",
+            ",",
+            "-f",
+            "1",
+            "--tab-output",
+            "--output-delimiter",
+            ":",
+        ],
+        "cannot be used with",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn group_separator() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-d", ",", "-f", "1-2,5-6", "--group-separator", " -- "])
+        .write_stdin("a,b,c,d,e,f\n1,\"2,x\",3,4,5,6\n")
+        .assert()
+        .success()
+        .stdout("a,b -- e,f\n1,\"2,x\" -- 5,6\n");
+    // Only between runs of fields, which don't always come from separate ranges
+    Command::cargo_bin(PRG)?
+        .args(["-d", ",", "-f", "1-2,3,6", "--group-separator", "|", "-N"])
+        .write_stdin("a,b,c,d,e,f\n")
+        .assert()
+        .success()
+        .stdout("1,a,b,c|f\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn stdin_between_files() -> TestResult {
+    let files = ["tests/inputs/pairs1.tsv", "-", "tests/inputs/pairs2.tsv"];
+    Command::cargo_bin(PRG)?
+        .args(["-f", "1", "--no-filename"])
+        .args(files)
+        .write_stdin("s1\tt1\ns2\tt2\n")
+        .assert()
+        .success()
+        .stdout("a1\na2\ns1\ns2\nx1\nx2\n");
+    Command::cargo_bin(PRG)?
+        .args(["-f", "1", "--paste"])
+        .args(files)
+        .write_stdin("s1\tt1\ns2\tt2\n")
+        .assert()
+        .success()
+        .stdout("a1\ts1\tx1\na2\ts2\tx2\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn repeat() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-d", ",", "-f", "2", "--repeat", "3"])
+        .write_stdin("a,b,c\nd,e,f\n")
+        .assert()
+        .success()
+        .stdout("b,b,b\ne,e,e\n");
+    // Each field of a range is repeated in turn, rather than the whole range
+    Command::cargo_bin(PRG)?
+        .args(["-d", ",", "-f", "1-2", "--repeat", "2"])
+        .write_stdin("a,b,c\n")
+        .assert()
+        .success()
+        .stdout("a,a,b,b\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn bad_repeat() -> TestResult {
+    dies(
+        &["-f", "1", "--repeat", "0"],
+        "kat: invalid repeat count: '0'",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn delim_char() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--delim-char", "│", "-f", "1,3"])
+        .write_stdin("a│b│c\nd,e│f│g\n")
+        .assert()
+        .success()
+        .stdout("a│c\nd,e│g\n");
+    // A character which is a byte is split on like any other delimiter
+    Command::cargo_bin(PRG)?
+        .args(["--delim-char", ",", "-f", "2"])
+        .write_stdin("a,\"b,c\"\n")
+        .assert()
+        .success()
+        .stdout("\"b,c\"\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn bad_delim_char() -> TestResult {
+    dies(
+        &["--delim-char", "││", "-f", "1"],
+        "kat: bad delimiter character: '││'",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn output_newline() -> TestResult {
+    for (mode, list, expected) in [
+        ("-f", "2", "b\r\ne\r\n"),
+        ("-c", "1", "a\r\nd\r\n"),
+        ("-b", "1", "a\r\nd\r\n"),
+    ] {
+        Command::cargo_bin(PRG)?
+            .args([mode, list, "--output-newline", "crlf"])
+            .write_stdin("a\tb\nd\te\r\n")
+            .assert()
+            .success()
+            .stdout(expected);
+    }
+    Command::cargo_bin(PRG)?
+        .args(["-f", "2", "--output-newline", "lf"])
+        .write_stdin("a\tb\r\n")
+        .assert()
+        .success()
+        .stdout("b\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn output_newline_with_null_output() -> TestResult {
+    dies(
+        &["-f", "1", "--output-newline", "crlf", "--null-output"],
+        "cannot be used with",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn skip_lines() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-f", "2", "--skip-lines", "2", "tests/inputs/comments.tsv"])
+        .assert()
+        .success()
+        .stdout("age\n30\n41\n");
+    // Skipped per file, not just once at the start
+    Command::cargo_bin(PRG)?
+        .args([
+            "-f",
+            "1",
+            "--skip-lines",
+            "3",
+            "--",
+            "-",
+            "tests/inputs/comments.tsv",
+        ])
+        .write_stdin("ignored\nignored\nignored\nzed\t9\n")
+        .assert()
+        .success()
+        .stdout("zed\n\n==> tests/inputs/comments.tsv <==\nann\nbob\n");
+    Command::cargo_bin(PRG)?
+        .args(["-f", "1", "--skip-lines", "9", "tests/inputs/comments.tsv"])
+        .assert()
+        .success()
+        .stdout("");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn skip_lines_before_header() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-f", "age", "--header-names", "--skip-lines", "2"])
+        .arg("tests/inputs/comments.tsv")
+        .assert()
+        .success()
+        .stdout("age\n30\n41\n");
+    Command::cargo_bin(PRG)?
+        .args(["-f", "1", "--header", "--skip-lines", "2"])
+        .arg("tests/inputs/comments.tsv")
+        .assert()
+        .success()
+        .stdout("name\nann\nbob\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_skip_lines() -> TestResult {
+    dies(
+        &["-f", "1", "--skip-lines", "two"],
+        "invalid number of lines: 'two'",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn undelimited_quoted_lines_as_they_are() -> TestResult {
+    // The second line's tab is quoted, so it's only the one field, and written out still quoted
+    Command::cargo_bin(PRG)?
+        .args(["-f", "2"])
+        .write_stdin("\"hello\"\n\"a\tb\"\nc\td\n\"say \"\"hi\"\"\"\n")
+        .assert()
+        .success()
+        .stdout("\"hello\"\n\"a\tb\"\nd\n\"say \"\"hi\"\"\"\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn max_output_bytes_whole_lines_nul_terminated() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args([
+            "-c1",
+            "--max-output-bytes",
+            "100",
+            "--whole-lines",
+            "--null-output",
+        ])
+        .write_stdin("a\nb\n")
+        .assert()
+        .success()
+        .stdout("a\0b\0")
+        .stderr("");
+    Command::cargo_bin(PRG)?
+        .args(["-c", "1-", "-z", "--max-output-bytes", "5", "--whole-lines"])
+        .write_stdin("aa\0bb\0cc\0")
+        .assert()
+        .success()
+        .stdout("aa\0")
+        .stderr("kat: stopped at the output limit of 5 bytes\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn split_output_nul_terminated() -> TestResult {
+    let prefix = std::env::temp_dir().join(random_string());
+    let prefix = prefix.to_str().unwrap();
+    Command::cargo_bin(PRG)?
+        .args(["-z", "-c", "1", "--split-output", prefix, "--parts", "2"])
+        .write_stdin("a1\0b1\0c1\0d1\0")
+        .assert()
+        .success()
+        .stdout("");
+
+    let first = fs::read_to_string(format!("{}.0", prefix))?;
+    let second = fs::read_to_string(format!("{}.1", prefix))?;
+    fs::remove_file(format!("{}.0", prefix))?;
+    fs::remove_file(format!("{}.1", prefix))?;
+    assert_eq!(first, "a\0c\0");
+    assert_eq!(second, "b\0d\0");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn interleave_zero_terminated() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args([
+            "--interleave",
+            "-z",
+            "-f",
+            "2",
+            "tests/inputs/pairs.nul",
+            "-",
+        ])
+        .write_stdin("c1\tc2\0")
+        .assert()
+        .success()
+        .stdout("a2\0c2\0b2\0");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn paste_output_delimiter() -> TestResult {
+    for (delimiter, expected) in [(":", "a1:x1\na2:x2\n"), ("::", "a1::x1\na2::x2\n")] {
+        Command::cargo_bin(PRG)?
+            .args(["--paste", "-f", "1", "--output-delimiter", delimiter])
+            .args([PAIRS1_TSV, PAIRS2_TSV])
+            .assert()
+            .success()
+            .stdout(expected);
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn paste_zero_terminated() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--paste", "-z", "-f", "2", "tests/inputs/pairs.nul", "-"])
+        .write_stdin("c1\tc2\0")
+        .assert()
+        .success()
+        .stdout("a2\tc2\0b2\t\0");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn skip_lines_counts_towards_line_numbers() -> TestResult {
+    for (list, expected) in [
+        ("-f", "3\tname\n4\tann\n5\tbob\n"),
+        ("-c", "3\tn\n4\ta\n5\tb\n"),
+        ("-b", "3\tn\n4\ta\n5\tb\n"),
+    ] {
+        Command::cargo_bin(PRG)?
+            .args([list, "1", "-N", "--skip-lines", "2"])
+            .arg("tests/inputs/comments.tsv")
+            .assert()
+            .success()
+            .stdout(expected);
+    }
+    Command::cargo_bin(PRG)?
+        .args(["-f", "3", "--strict-ranges", "--skip-lines", "2"])
+        .arg("tests/inputs/comments.tsv")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("line 3 has 2 fields"));
+    Command::cargo_bin(PRG)?
+        .args(["-b", "1", "--strict-utf8", "--skip-lines", "2"])
+        .write_stdin("#\n#\n\u{e9}\n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "line 3 cuts down to invalid UTF-8",
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[cfg(all(feature = "zstd", feature = "bzip2"))]
+#[test]
+fn decompress_stdin_zstd_bz2() -> TestResult {
+    use std::io::Write;
+
+    let zstd = zstd::encode_all(&b"a\tb\nc\td\n"[..], 0)?;
+    let mut encoder = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+    encoder.write_all(b"a\tb\nc\td\n")?;
+    for (format, compressed) in [("zstd", zstd), ("bz2", encoder.finish()?)] {
+        Command::cargo_bin(PRG)?
+            .args(["-f", "2", "--decompress", format])
+            .write_stdin(compressed)
+            .assert()
+            .success()
+            .stdout("b\nd\n");
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn stats_count_skipped_lines_where_dropped() -> TestResult {
+    // The banners are written but weren't read, while a line is skipped by -s or --skip-lines
+    Command::cargo_bin(PRG)?
+        .args(["-f", "2", "-s", "--stats", "--skip-lines", "1"])
+        .args(["tests/inputs/plain.tsv", "tests/inputs/comments.tsv"])
+        .assert()
+        .success()
+        .stderr("lines read: 9\nlines written: 7\nlines skipped: 5\nbytes written: 78\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn header_names_default_for_missing_column() -> TestResult {
+    // typed.csv has no email column, which is filled in with the default
+    Command::cargo_bin(PRG)?
+        .args(["-d", ",", "-f", "id,email", "--header-names"])
+        .args(["--default", "email=none"])
+        .args(["tests/inputs/contacts.csv", "tests/inputs/typed.csv"])
+        .assert()
+        .success()
+        .stdout(concat!(
+            "==> tests/inputs/contacts.csv <==\n",
+            "id,email\n1,ann@example.com\n2,bob@example.com\n\n",
+            "==> tests/inputs/typed.csv <==\n",
+            "id,email\n1,none\n2,none\n3,none\n",
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn header_names_glob() -> TestResult {
+    // Every metric_* column, in header order, along with a name given exactly
+    Command::cargo_bin(PRG)?
+        .args([
+            "-d",
+            ",",
+            "-f",
+            "metric_*,id",
+            "--header-names",
+            "--keep-order",
+        ])
+        .write_stdin("id,metric_a,name,metric_b,metrics\n1,10,x,20,30\n")
+        .assert()
+        .success()
+        .stdout("metric_a,metric_b,id\n10,20,1\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn huge_stepped_range() -> TestResult {
+    // Stepping by 1 is the range as it is, while a bigger step isn't expanded past a point
+    Command::cargo_bin(PRG)?
+        .args(["-f", "1-9223372036854775807:1", "tests/inputs/abc.tsv"])
+        .assert()
+        .success()
+        .stdout("a\tb\tc\n");
+    dies(
+        &["-f", "1-9223372036854775807:2", "tests/inputs/abc.tsv"],
+        "kat: too many positions in '1-9223372036854775807:2'",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_position_too_large() -> TestResult {
+    // It would otherwise count back from the end of the line, as if under --tail-anchored
+    dies(
+        &["-f", "9223372036854775808", "tests/inputs/abc.tsv"],
+        "kat: position too large: '9223372036854775808'",
+    )?;
+    dies(
+        &["-f", "1-18446744073709551615", "tests/inputs/abc.tsv"],
+        "kat: position too large: '18446744073709551615'",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn huge_closed_range() -> TestResult {
+    // The fields it runs over aren't listed out one by one unless they're being counted
+    Command::cargo_bin(PRG)?
+        .args(["-f", "2-9000000000000000000", "tests/inputs/abc.tsv"])
+        .assert()
+        .success()
+        .stdout("b\tc\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[cfg(feature = "hash")]
+#[test]
+fn hash_fields_only_where_selected() -> TestResult {
+    // The name field is hashed but not selected, so it isn't written out at all
+    Command::cargo_bin(PRG)?
+        .args([TYPED_CSV, "-d", ",", "-f", "1", "--hash-fields", "3"])
+        .assert()
+        .success()
+        .stdout("id\n1\n2\n3\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[cfg(feature = "ndjson")]
+#[test]
+fn no_filename_banners_in_json() -> TestResult {
+    // Each line has to be an object of its own
+    Command::cargo_bin(PRG)?
+        .args(["-f", "1", "--output-format", "json", PAIRS1_TSV, PAIRS2_TSV])
+        .assert()
+        .success()
+        .stdout("{\"1\":\"a1\"}\n{\"1\":\"a2\"}\n{\"1\":\"x1\"}\n{\"1\":\"x2\"}\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn no_filename_banners_in_split_output() -> TestResult {
+    // Otherwise they'd be dealt out as if they were lines
+    let prefix = std::env::temp_dir().join(random_string());
+    let prefix = prefix.to_str().unwrap();
+    Command::cargo_bin(PRG)?
+        .args(["-f", "1", "--split-output", prefix, "--parts", "2"])
+        .args([PAIRS1_TSV, PAIRS2_TSV])
+        .assert()
+        .success()
+        .stdout("");
+
+    let first = fs::read_to_string(format!("{}.0", prefix))?;
+    let second = fs::read_to_string(format!("{}.1", prefix))?;
+    fs::remove_file(format!("{}.0", prefix))?;
+    fs::remove_file(format!("{}.1", prefix))?;
+    assert_eq!(first, "a1\nx1\n");
+    assert_eq!(second, "a2\nx2\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_with_filename_where_banners_dont_fit() -> TestResult {
+    for args in [
+        &["--output-format", "json"][..],
+        &["--split-output", "out", "--parts", "2"],
+        &["--paste"],
+        &["--interleave"],
+    ] {
+        Command::cargo_bin(PRG)?
+            .args(["-f", "1", "--with-filename", PAIRS1_TSV, PAIRS2_TSV])
+            .args(args)
+            .assert()
+            .failure()
+            .stderr(
+                predicate::str::contains("can't be used with")
+                    .or(predicate::str::contains("cannot be used with")),
+            );
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn strict_csv_long_output_delimiter() -> TestResult {
+    // Fields are quoted if they have the whole delimiter in them, or a quote
+    Command::cargo_bin(PRG)?
+        .args([
+            "-d",
+            ",",
+            "-f",
+            "1,2",
+            "--output-delimiter",
+            ";;",
+            "--strict-csv",
+        ])
+        .write_stdin("a,\"b\"\"q\"\na,\"b;;c\"\na,b;c\n")
+        .assert()
+        .success()
+        .stdout("a;;\"b\"\"q\"\na;;\"b;;c\"\na;;b;c\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn describe_splits_as_cut_does() -> TestResult {
+    // The fields described are those -f would cut, however the lines are split
+    for (args, input) in [
+        (&["--str-delim", "::"][..], "name::n\nx::1\ny::\n"),
+        (&["-z", "-d", " "], "name n\0x 1\0y \0"),
+    ] {
+        Command::cargo_bin(PRG)?
+            .args(["-f", "1,2", "--describe"])
+            .args(args)
+            .write_stdin(input)
+            .assert()
+            .success()
+            .stdout("column\ttype\tnulls\tdistinct\nname\tstring\t0\t2\nn\tint\t1\t1\n");
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn color_quoted_field() -> TestResult {
+    // The quoted comma doesn't split the field, just as when it's cut
+    Command::cargo_bin(PRG)?
+        .args(["-d", ",", "-f", "2", "--color", "always"])
+        .write_stdin("a,\"b,c\",d\n\"x\"\"y,z\",q\n")
+        .assert()
+        .success()
+        .stdout("a,\x1b[1;31m\"b,c\"\x1b[0m,d\n\"x\"\"y,z\",\x1b[1;31mq\x1b[0m\n");
+    Ok(())
+}